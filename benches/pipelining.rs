@@ -0,0 +1,248 @@
+//! Compare socket writes for a pipelined transaction against the same
+//! commands sent in lock step.
+//!
+//! Run with `cargo bench --bench pipelining`.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use bytes::BytesMut;
+use futures::channel::oneshot;
+use futures::future::{FutureExt, TryFutureExt};
+use futures::stream::{Stream, TryStreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::runtime::Runtime;
+
+use rustyknife::rfc5321::{ForwardPath, ReversePath};
+use rustyknife::types::{Domain, DomainPart};
+use smtpbis::{
+    smtp_server, Config, EhloKeywords, Envelope, Handler, LineError, MailParams, RcptParams,
+    Response, ServerError, Session,
+};
+
+const RECIPIENTS: usize = 50;
+const ITERATIONS: u32 = 2000;
+
+/// In-memory socket counting the writes that would be syscalls.
+struct CountingSocket {
+    input: Vec<Vec<u8>>,
+    next: usize,
+    writes: usize,
+    written: usize,
+}
+
+impl CountingSocket {
+    fn new(input: Vec<Vec<u8>>) -> Self {
+        Self {
+            input,
+            next: 0,
+            writes: 0,
+            written: 0,
+        }
+    }
+}
+
+impl AsyncRead for CountingSocket {
+    /// Every read returns one of the input segments, like a client
+    /// waiting for replies between them would produce.
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let segment = match this.input.get_mut(this.next) {
+            Some(segment) => segment,
+            None => return Poll::Ready(Ok(0)),
+        };
+        let len = std::cmp::min(buf.len(), segment.len());
+
+        buf[..len].copy_from_slice(&segment[..len]);
+        segment.drain(..len);
+        if segment.is_empty() {
+            this.next += 1;
+        }
+
+        Poll::Ready(Ok(len))
+    }
+}
+
+impl AsyncWrite for CountingSocket {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.writes += 1;
+        self.written += buf.len();
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+struct BenchHandler;
+
+#[async_trait]
+impl Handler for BenchHandler {
+    type TlsConfig = ();
+    type TlsSession = ();
+    type SessionData = ();
+
+    async fn ehlo(
+        &mut self,
+        _session: &Session<Self::SessionData>,
+        _domain: DomainPart,
+        initial_keywords: EhloKeywords,
+    ) -> Result<(String, EhloKeywords), Response> {
+        Ok(("hello".into(), initial_keywords))
+    }
+
+    async fn helo(
+        &mut self,
+        _session: &Session<Self::SessionData>,
+        _domain: Domain,
+    ) -> Option<Response> {
+        None
+    }
+
+    async fn rset(&mut self, _session: &Session<Self::SessionData>) {}
+
+    async fn mail(
+        &mut self,
+        _session: &Session<Self::SessionData>,
+        _path: ReversePath,
+        _params: MailParams,
+    ) -> Option<Response> {
+        None
+    }
+
+    async fn rcpt(
+        &mut self,
+        _session: &Session<Self::SessionData>,
+        _path: ForwardPath,
+        _params: RcptParams,
+    ) -> Option<Response> {
+        None
+    }
+
+    async fn data<S>(
+        &mut self,
+        _session: &Session<Self::SessionData>,
+        stream: &mut S,
+        _envelope: &Envelope,
+    ) -> Result<Option<Response>, ServerError>
+    where
+        S: Stream<Item = Result<BytesMut, LineError>> + Unpin + Send,
+    {
+        while stream.try_next().await?.is_some() {}
+        Ok(None)
+    }
+
+    async fn bdat<S>(
+        &mut self,
+        _session: &Session<Self::SessionData>,
+        stream: &mut S,
+        _size: u64,
+        _last: bool,
+        _envelope: &Envelope,
+    ) -> Result<Option<Response>, ServerError>
+    where
+        S: Stream<Item = Result<BytesMut, LineError>> + Unpin + Send,
+    {
+        while stream.try_next().await?.is_some() {}
+        Ok(None)
+    }
+}
+
+/// Client commands in the groups a pipelining client sends them,
+/// waiting for replies after each group.
+fn transaction() -> Vec<Vec<u8>> {
+    let mut envelope = b"MAIL FROM:<sender@example.org>\r\n".to_vec();
+    for i in 0..RECIPIENTS {
+        envelope.extend(format!("RCPT TO:<rcpt{}@example.org>\r\n", i).as_bytes());
+    }
+    envelope.extend(b"DATA\r\n");
+
+    vec![
+        b"EHLO client.example.org\r\n".to_vec(),
+        envelope,
+        b"Subject: bench\r\n\r\nbody\r\n.\r\n".to_vec(),
+        b"QUIT\r\n".to_vec(),
+    ]
+}
+
+/// The same commands, one per read.
+fn lock_step() -> Vec<Vec<u8>> {
+    transaction()
+        .into_iter()
+        .flat_map(|group| {
+            group
+                .split_inclusive(|b| *b == b'\n')
+                .map(<[u8]>::to_vec)
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Serve one session, returning the number of writes and bytes.
+fn session(rt: &mut Runtime, input: Vec<Vec<u8>>) -> (usize, usize) {
+    let mut socket = CountingSocket::new(input);
+    let mut session = Session::new(None, None, ());
+    let config = Config::default();
+    let (_shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let mut shutdown = shutdown_rx.map_err(|_| ()).fuse();
+
+    rt.block_on(smtp_server(
+        &mut socket,
+        &mut BenchHandler,
+        &mut session,
+        &config,
+        &mut shutdown,
+        true,
+    ))
+    .unwrap();
+
+    (socket.writes, socket.written)
+}
+
+/// Time sessions with `input`, checking that they take
+/// `expected_writes` writes.
+fn bench(rt: &mut Runtime, name: &str, input: fn() -> Vec<Vec<u8>>, expected_writes: usize) {
+    let (writes, written) = session(rt, input());
+    assert_eq!(writes, expected_writes, "{} writes", name);
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        session(rt, input());
+    }
+    let elapsed: Duration = start.elapsed() / ITERATIONS;
+
+    println!(
+        "{:<10} {:>4} writes, {:>5} bytes, {:>8.1?} per session",
+        name, writes, written, elapsed
+    );
+}
+
+fn main() {
+    let mut rt = tokio::runtime::Builder::new()
+        .basic_scheduler()
+        .enable_time()
+        .build()
+        .unwrap();
+
+    println!("MAIL + {} RCPT + DATA + QUIT", RECIPIENTS);
+    // Banner, EHLO, the envelope group, the message and QUIT.
+    bench(&mut rt, "pipelined", transaction, 5);
+    // One write per reply.
+    bench(&mut rt, "lock-step", lock_step, RECIPIENTS + 6);
+}
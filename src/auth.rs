@@ -0,0 +1,225 @@
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use md5::Md5;
+
+use crate::{Reply, ScramCredentialStore, ScramSha1, ScramSha256};
+
+/// Server side of a SASL mechanism used by the AUTH command (RFC 4954).
+///
+/// A new instance is created for every AUTH exchange.
+pub trait SaslMechanism: Send {
+    /// Process the next client response.
+    ///
+    /// `response` is `None` when the client did not send an initial
+    /// response with the AUTH command. An initial response of `"="`
+    /// is passed as an empty slice.
+    fn step(&mut self, response: Option<&[u8]>) -> SaslStep;
+}
+
+pub enum SaslStep {
+    /// Send this challenge to the client and wait for a response.
+    Challenge(Vec<u8>),
+    /// The exchange is complete, the credentials must now be checked
+    /// by [`crate::Handler::authenticate`].
+    Done(Credentials),
+    /// Abort the exchange with this reply.
+    Failed(Reply),
+}
+
+/// Credentials collected by a SASL exchange.
+#[derive(Debug)]
+pub enum Credentials {
+    /// Cleartext password from PLAIN or LOGIN.
+    Password {
+        authzid: Option<String>,
+        authcid: String,
+        password: String,
+    },
+    /// CRAM-MD5 response to `challenge`.
+    CramMd5 {
+        username: String,
+        challenge: String,
+        digest: String,
+    },
+    /// Identity already proven by the mechanism, such as SCRAM.
+    Verified {
+        authzid: Option<String>,
+        authcid: String,
+    },
+}
+
+impl Credentials {
+    /// The authentication identity supplied by the client.
+    pub fn username(&self) -> &str {
+        match self {
+            Self::Password { authcid, .. } => authcid,
+            Self::CramMd5 { username, .. } => username,
+            Self::Verified { authcid, .. } => authcid,
+        }
+    }
+
+    /// Check the credentials against the user's cleartext password.
+    ///
+    /// Always false for `Verified` credentials: the mechanism checked
+    /// them against the credential store, there is no password to
+    /// compare.
+    pub fn verify_password(&self, expected: &str) -> bool {
+        match self {
+            Self::Password { password, .. } => {
+                constant_time_eq(password.as_bytes(), expected.as_bytes())
+            }
+            Self::CramMd5 {
+                challenge, digest, ..
+            } => {
+                let mut mac = Hmac::<Md5>::new_from_slice(expected.as_bytes()).unwrap();
+                mac.update(challenge.as_bytes());
+                let computed = hex(&mac.finalize().into_bytes());
+
+                constant_time_eq(computed.as_bytes(), digest.to_ascii_lowercase().as_bytes())
+            }
+            Self::Verified { .. } => false,
+        }
+    }
+}
+
+/// Instantiate one of the mechanisms shipped with this library.
+///
+/// `name` must be uppercase. `hostname` goes in the CRAM-MD5
+/// challenge. The SCRAM mechanisms are only available with a
+/// credential store.
+pub fn builtin_mechanism(
+    name: &str,
+    hostname: &str,
+    scram_store: Option<Arc<dyn ScramCredentialStore>>,
+) -> Option<Box<dyn SaslMechanism>> {
+    match (name, scram_store) {
+        ("PLAIN", _) => Some(Box::new(Plain)),
+        ("LOGIN", _) => Some(Box::new(Login::default())),
+        ("CRAM-MD5", _) => Some(Box::new(CramMd5::new(hostname))),
+        ("SCRAM-SHA-1", Some(store)) => Some(Box::new(ScramSha1::new(store))),
+        ("SCRAM-SHA-256", Some(store)) => Some(Box::new(ScramSha256::new(store))),
+        _ => None,
+    }
+}
+
+/// The PLAIN mechanism from RFC 4616.
+pub struct Plain;
+
+impl SaslMechanism for Plain {
+    fn step(&mut self, response: Option<&[u8]>) -> SaslStep {
+        let response = match response {
+            Some(response) => response,
+            None => return SaslStep::Challenge(Vec::new()),
+        };
+
+        let mut fields = response.split(|c| *c == 0).map(std::str::from_utf8);
+
+        match (fields.next(), fields.next(), fields.next(), fields.next()) {
+            (Some(Ok(authzid)), Some(Ok(authcid)), Some(Ok(password)), None)
+                if !authcid.is_empty() =>
+            {
+                SaslStep::Done(Credentials::Password {
+                    authzid: if authzid.is_empty() {
+                        None
+                    } else {
+                        Some(authzid.into())
+                    },
+                    authcid: authcid.into(),
+                    password: password.into(),
+                })
+            }
+            _ => SaslStep::Failed(Reply::auth_malformed()),
+        }
+    }
+}
+
+/// The obsolete but widely deployed LOGIN mechanism.
+#[derive(Default)]
+pub struct Login {
+    username: Option<String>,
+}
+
+impl SaslMechanism for Login {
+    fn step(&mut self, response: Option<&[u8]>) -> SaslStep {
+        let response = match response.map(std::str::from_utf8) {
+            Some(Ok(response)) => response,
+            Some(Err(_)) => return SaslStep::Failed(Reply::auth_malformed()),
+            None => return SaslStep::Challenge(b"Username:".to_vec()),
+        };
+
+        match self.username.take() {
+            None => {
+                self.username = Some(response.into());
+                SaslStep::Challenge(b"Password:".to_vec())
+            }
+            Some(username) => SaslStep::Done(Credentials::Password {
+                authzid: None,
+                authcid: username,
+                password: response.into(),
+            }),
+        }
+    }
+}
+
+/// The CRAM-MD5 mechanism from RFC 2195.
+pub struct CramMd5 {
+    challenge: String,
+    sent: bool,
+}
+
+impl CramMd5 {
+    pub fn new(hostname: &str) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        Self {
+            challenge: format!("<{}.{}@{}>", rand::random::<u32>(), timestamp, hostname),
+            sent: false,
+        }
+    }
+}
+
+impl SaslMechanism for CramMd5 {
+    fn step(&mut self, response: Option<&[u8]>) -> SaslStep {
+        if !self.sent {
+            // No initial response is allowed with CRAM-MD5.
+            if response.is_some() {
+                return SaslStep::Failed(Reply::auth_malformed());
+            }
+            self.sent = true;
+            return SaslStep::Challenge(self.challenge.as_bytes().to_vec());
+        }
+
+        let response = match response.map(std::str::from_utf8) {
+            Some(Ok(response)) => response,
+            _ => return SaslStep::Failed(Reply::auth_malformed()),
+        };
+
+        match response.rsplitn(2, ' ').collect::<Vec<_>>().as_slice() {
+            [digest, username]
+                if !username.is_empty()
+                    && digest.len() == 32
+                    && digest.chars().all(|c| c.is_ascii_hexdigit()) =>
+            {
+                SaslStep::Done(Credentials::CramMd5 {
+                    username: (*username).into(),
+                    challenge: self.challenge.clone(),
+                    digest: (*digest).into(),
+                })
+            }
+            _ => SaslStep::Failed(Reply::auth_malformed()),
+        }
+    }
+}
+
+fn hex(input: &[u8]) -> String {
+    input.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
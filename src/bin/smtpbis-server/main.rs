@@ -3,6 +3,7 @@
 use std::io::Cursor;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use async_trait::async_trait;
 use bytes::BytesMut;
@@ -14,32 +15,47 @@ use futures_util::stream::Stream;
 use futures_util::stream::TryStreamExt;
 
 use tokio::net::{TcpListener, TcpStream};
-use tokio::prelude::*;
 use tokio::runtime::Runtime;
 use tokio::sync::oneshot::Receiver;
 
 use tokio_rustls::rustls::{
     internal::pemfile::{certs, rsa_private_keys},
-    NoClientAuth, ServerConfig, ServerSession, Session,
+    NoClientAuth, ServerConfig, ServerSession, Session as _,
 };
-use tokio_rustls::TlsAcceptor;
 
-use rustyknife::rfc5321::{ForwardPath, Param, Path, ReversePath};
+use rustyknife::rfc5321::{ForwardPath, Path, ReversePath};
 use rustyknife::types::{Domain, DomainPart};
 use smtpbis::{
-    smtp_server, Config, EhloKeywords, Handler, LineError, LoopExit, Reply, ServerError,
-    ShutdownSignal,
+    smtp_session, smtps_server, BareNewline, Config, Credentials, EhloKeywords, EnhancedCode,
+    Envelope, Handler, LineCodec, LineError, MailParams, PipeliningViolation, ProxyHeader,
+    RcptParams, Reply, Response, RustlsAcceptor, ScramCredentialStore, ScramCredentials,
+    ServerError, Session, ShutdownSignal, Timeouts, TransactionOutcome, Xclient, Xforward,
 };
 
 const CERT: &[u8] = include_bytes!("../../../data/testcert.pem");
 const KEY: &[u8] = include_bytes!("../../../data/testcert.key");
 
+struct DummyScramStore;
+
+impl ScramCredentialStore for DummyScramStore {
+    fn lookup(&self, mechanism: &str, username: &str) -> Option<ScramCredentials> {
+        println!("SCRAM lookup: {} {}", mechanism, username);
+        let salt = username.as_bytes();
+
+        match mechanism {
+            "SCRAM-SHA-1" => Some(ScramCredentials::from_password::<sha1::Sha1>(
+                "password", salt, 4096,
+            )),
+            "SCRAM-SHA-256" => Some(ScramCredentials::from_password::<sha2::Sha256>(
+                "password", salt, 4096,
+            )),
+            _ => None,
+        }
+    }
+}
+
 struct DummyHandler {
     tls_config: Arc<ServerConfig>,
-    addr: SocketAddr,
-    helo: Option<DomainPart>,
-    mail: Option<ReversePath>,
-    rcpt: Vec<ForwardPath>,
     body: Vec<u8>,
 }
 
@@ -47,73 +63,115 @@ struct DummyHandler {
 impl Handler for DummyHandler {
     type TlsConfig = Arc<ServerConfig>;
     type TlsSession = ServerSession;
+    /// Name of the listener.
+    type SessionData = &'static str;
 
-    async fn tls_request(&mut self) -> Option<Self::TlsConfig> {
+    async fn tls_request(
+        &mut self,
+        _session: &Session<Self::SessionData>,
+    ) -> Option<Self::TlsConfig> {
         Some(self.tls_config.clone())
     }
 
-    async fn tls_started(&mut self, session: &Self::TlsSession) {
+    async fn tls_started(
+        &mut self,
+        _session: &Session<Self::SessionData>,
+        tls_session: &Self::TlsSession,
+    ) {
         println!(
             "TLS started: {:?}/{:?}",
-            session.get_protocol_version(),
-            session.get_negotiated_ciphersuite()
+            tls_session.get_protocol_version(),
+            tls_session.get_negotiated_ciphersuite()
         );
-        self.reset_tx();
     }
 
     async fn ehlo(
         &mut self,
+        session: &Session<Self::SessionData>,
         domain: DomainPart,
         mut initial_keywords: EhloKeywords,
-    ) -> Result<(String, EhloKeywords), Reply> {
-        initial_keywords.insert("DSN".into(), None);
+    ) -> Result<(String, EhloKeywords), Response> {
         initial_keywords.insert("8BITMIME".into(), None);
-        initial_keywords.insert("SIZE".into(), Some("73400320".into()));
 
-        let greet = format!("hello {} from {}", domain, self.addr);
-        self.helo = Some(domain);
-        self.reset_tx();
+        let greet = match session.peer_addr() {
+            Some(addr) => format!("hello {} from {} on {}", domain, addr, session.data()),
+            None => format!("hello {} on {}", domain, session.data()),
+        };
 
         Ok((greet, initial_keywords))
     }
 
-    async fn helo(&mut self, domain: Domain) -> Option<Reply> {
-        self.helo = Some(DomainPart::Domain(domain));
-        self.reset_tx();
-
+    async fn helo(
+        &mut self,
+        _session: &Session<Self::SessionData>,
+        _domain: Domain,
+    ) -> Option<Response> {
         None
     }
 
-    async fn mail(&mut self, path: ReversePath, _params: Vec<Param>) -> Option<Reply> {
-        println!("Handler MAIL: {:?}", path);
-
-        self.mail = Some(path);
+    async fn mail(
+        &mut self,
+        session: &Session<Self::SessionData>,
+        path: ReversePath,
+        params: MailParams,
+    ) -> Option<Response> {
+        println!(
+            "Handler MAIL #{} from {:?}: {:?} {:?}",
+            session.transactions(),
+            session.helo().map(ToString::to_string),
+            path,
+            params
+        );
         None
     }
 
-    async fn rcpt(&mut self, path: ForwardPath, _params: Vec<Param>) -> Option<Reply> {
-        println!("Handler RCPT: {:?}", path);
+    async fn rcpt(
+        &mut self,
+        session: &Session<Self::SessionData>,
+        path: ForwardPath,
+        params: RcptParams,
+    ) -> Option<Response> {
+        println!("Handler RCPT: {:?} {:?}", path, params);
         if let ForwardPath::Path(Path(mbox, _)) = &path {
             if let DomainPart::Domain(domain) = mbox.domain_part() {
                 if domain.starts_with('z') {
-                    return Some(Reply::new(550, None, "I don't like zeds"));
+                    if session.rejected_recipients() >= 2 {
+                        return Some(Response::disconnect(Reply::new(
+                            421,
+                            Some(EnhancedCode(4, 7, 0)),
+                            "Too many bad recipients",
+                        )));
+                    }
+                    return Some(Reply::new(550, None, "I don't like zeds").into());
                 }
             }
         };
-        self.rcpt.push(path);
         None
     }
 
-    async fn data_start(&mut self) -> Option<Reply> {
-        println!("Handler DATA start");
+    async fn data_start(
+        &mut self,
+        _session: &Session<Self::SessionData>,
+        envelope: &Envelope,
+    ) -> Option<Response> {
+        println!(
+            "Handler DATA start from {:?} to {} recipients",
+            envelope.reverse_path,
+            envelope.recipients.len()
+        );
         None
     }
 
-    async fn data<S>(&mut self, stream: &mut S) -> Result<Option<Reply>, ServerError>
+    async fn data<S>(
+        &mut self,
+        _session: &Session<Self::SessionData>,
+        stream: &mut S,
+        envelope: &Envelope,
+    ) -> Result<Option<Response>, ServerError>
     where
         S: Stream<Item = Result<BytesMut, LineError>> + Unpin + Send,
     {
-        println!("Handler DATA read");
+        println!("Handler DATA read: {:?}", envelope);
         let mut nb_lines: usize = 0;
         self.body.clear();
 
@@ -124,17 +182,18 @@ impl Handler for DummyHandler {
 
         println!("got {} body lines", nb_lines);
         let reply_txt = format!("Received {} bytes in {} lines.", self.body.len(), nb_lines);
-        self.reset_tx();
 
-        Ok(Some(Reply::new(250, None, reply_txt)))
+        Ok(Some(Reply::new(250, None, reply_txt).into()))
     }
 
     async fn bdat<S>(
         &mut self,
+        _session: &Session<Self::SessionData>,
         stream: &mut S,
         _size: u64,
         last: bool,
-    ) -> Result<Option<Reply>, ServerError>
+        _envelope: &Envelope,
+    ) -> Result<Option<Response>, ServerError>
     where
         S: Stream<Item = Result<BytesMut, LineError>> + Unpin + Send,
     {
@@ -142,24 +201,128 @@ impl Handler for DummyHandler {
             self.body.extend(chunk)
         }
         if last {
-            self.reset_tx();
+            println!("got {} body bytes", self.body.len());
         }
 
         Ok(None)
     }
 
-    async fn rset(&mut self) {
-        self.reset_tx();
+    async fn message<S>(
+        &mut self,
+        _session: &Session<Self::SessionData>,
+        stream: &mut S,
+        envelope: &Envelope,
+    ) -> Result<Option<Response>, ServerError>
+    where
+        S: Stream<Item = Result<BytesMut, LineError>> + Unpin + Send,
+    {
+        println!("Handler message: {:?}", envelope);
+        self.body.clear();
+
+        while let Some(chunk) = stream.try_next().await? {
+            self.body.extend(chunk);
+        }
+
+        let reply_txt = format!("Received {} bytes.", self.body.len());
+        Ok(Some(Reply::new(250, None, reply_txt).into()))
+    }
+
+    async fn bare_newline(&mut self, _session: &Session<Self::SessionData>, bare: &BareNewline) {
+        println!("Handler bare newline: {:?}", bare);
+    }
+
+    async fn pipelining_violation(
+        &mut self,
+        _session: &Session<Self::SessionData>,
+        violation: &PipeliningViolation,
+    ) {
+        println!("Handler pipelining violation: {:?}", violation);
     }
-}
 
-impl DummyHandler {
-    fn reset_tx(&mut self) {
-        println!("Reset!");
-        self.mail = None;
-        self.rcpt.clear();
+    async fn early_talker(
+        &mut self,
+        _session: &Session<Self::SessionData>,
+        data: &[u8],
+    ) -> Option<Reply> {
+        println!("Handler early talker: {:?}", String::from_utf8_lossy(data));
+        Some(Reply::new(554, None, "Talking before the greeting"))
+    }
+
+    async fn rset(&mut self, _session: &Session<Self::SessionData>) {
+        println!("Handler RSET");
+    }
+
+    async fn transaction_end(
+        &mut self,
+        _session: &Session<Self::SessionData>,
+        envelope: &Envelope,
+        outcome: TransactionOutcome,
+    ) {
+        println!(
+            "Transaction {:?}: {} recipients",
+            outcome,
+            envelope.recipients.len()
+        );
         self.body.clear();
     }
+
+    fn scram_credential_store(&self) -> Option<Arc<dyn ScramCredentialStore>> {
+        Some(Arc::new(DummyScramStore))
+    }
+
+    async fn proxy(&mut self, _session: &Session<Self::SessionData>, header: &ProxyHeader) {
+        println!("Handler PROXY: {:?}", header);
+    }
+
+    async fn xclient_allowed(&mut self, session: &Session<Self::SessionData>) -> bool {
+        session
+            .socket_peer_addr()
+            .is_some_and(|a| a.ip().is_loopback())
+    }
+
+    async fn xclient(
+        &mut self,
+        _session: &Session<Self::SessionData>,
+        attributes: &Xclient,
+    ) -> Option<Response> {
+        println!("Handler XCLIENT: {:?}", attributes);
+        None
+    }
+
+    async fn xforward_allowed(&mut self, session: &Session<Self::SessionData>) -> bool {
+        session
+            .socket_peer_addr()
+            .is_some_and(|a| a.ip().is_loopback())
+    }
+
+    async fn xforward(
+        &mut self,
+        _session: &Session<Self::SessionData>,
+        attributes: &Xforward,
+    ) -> Option<Response> {
+        println!("Handler XFORWARD: {:?}", attributes);
+        None
+    }
+
+    async fn authenticate(
+        &mut self,
+        _session: &Session<Self::SessionData>,
+        credentials: Credentials,
+    ) -> Result<String, Reply> {
+        println!("Handler AUTH: {:?}", credentials.username());
+
+        let valid = match credentials {
+            // Already checked against DummyScramStore.
+            Credentials::Verified { .. } => true,
+            _ => credentials.verify_password("password"),
+        };
+
+        if valid {
+            Ok(credentials.username().into())
+        } else {
+            Err(Reply::auth_failed())
+        }
+    }
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -167,10 +330,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     rt.block_on(async {
         let (listen_shutdown_tx, listen_shutdown_rx) = tokio::sync::oneshot::channel();
-        tokio::spawn(listen_loop(listen_shutdown_rx));
+        tokio::spawn(listen_loop("127.0.0.1:8080", false, listen_shutdown_rx));
+        let (smtps_shutdown_tx, smtps_shutdown_rx) = tokio::sync::oneshot::channel();
+        tokio::spawn(listen_loop("127.0.0.1:8465", true, smtps_shutdown_rx));
 
         tokio::signal::ctrl_c().await.unwrap();
         listen_shutdown_tx.send(()).unwrap();
+        smtps_shutdown_tx.send(()).unwrap();
         println!("Waiting for tasks to finish...");
         // FIXME: actually wait on tasks here.
     });
@@ -178,8 +344,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-async fn listen_loop(mut shutdown: Receiver<()>) {
-    let mut listener = TcpListener::bind("127.0.0.1:8080").await.unwrap();
+async fn listen_loop(listen_addr: &str, implicit_tls: bool, mut shutdown: Receiver<()>) {
+    let mut listener = TcpListener::bind(listen_addr).await.unwrap();
 
     let mut tls_config = ServerConfig::new(NoClientAuth::new());
     let certs = certs(&mut Cursor::new(CERT)).unwrap();
@@ -200,7 +366,8 @@ async fn listen_loop(mut shutdown: Receiver<()>) {
                 let tls_config = tls_config.clone();
 
                 tokio::spawn(async move {
-                    let smtp_res = serve_smtp(socket, addr, tls_config, &mut shutdown_rx).await;
+                    let smtp_res =
+                        serve_smtp(socket, addr, tls_config, implicit_tls, &mut shutdown_rx).await;
                     println!("SMTP task done: {:?}", smtp_res);
                 })
             }
@@ -214,34 +381,67 @@ async fn listen_loop(mut shutdown: Receiver<()>) {
 }
 
 async fn serve_smtp(
-    mut socket: TcpStream,
+    socket: TcpStream,
     addr: SocketAddr,
     tls_config: Arc<ServerConfig>,
+    implicit_tls: bool,
     shutdown: &mut ShutdownSignal,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let mut handler = DummyHandler {
-        addr,
         tls_config,
-        helo: None,
-        mail: None,
-        rcpt: Vec::new(),
         body: Vec::new(),
     };
 
-    let mut config = Config::default();
-    match smtp_server(&mut socket, &mut handler, &config, shutdown, true).await {
-        Ok(LoopExit::Done) => println!("Server done"),
-        Ok(LoopExit::STARTTLS(tls_config)) => {
-            let acceptor = TlsAcceptor::from(tls_config);
-            let mut tls_socket = acceptor.accept(socket).await?;
-            config.enable_starttls = false;
-            handler.tls_started(tls_socket.get_ref().1).await;
-            match smtp_server(&mut tls_socket, &mut handler, &config, shutdown, false).await {
-                Ok(_) => println!("TLS Server done"),
-                Err(e) => println!("TLS Top level error: {:?}", e),
-            }
-            tls_socket.shutdown().await?;
+    let config = Config {
+        auth_mechanisms: ["PLAIN", "LOGIN", "CRAM-MD5", "SCRAM-SHA-1", "SCRAM-SHA-256"]
+            .iter()
+            .map(|m| (*m).into())
+            .collect(),
+        max_message_size: Some(73400320),
+        enable_dsn: true,
+        unified_message: true,
+        greeting_delay: Some(Duration::from_millis(200)),
+        line_codec: LineCodec::builder().max_chunk_size(4 * 1024 * 1024).build(),
+        timeouts: Timeouts {
+            session: Some(Duration::from_secs(30 * 60)),
+            min_data_rate: Some(1024),
+            ..Timeouts::default()
+        },
+        ..Config::default()
+    };
+
+    let local_addr = socket.local_addr().ok();
+    let listener = if implicit_tls { "smtps" } else { "smtp" };
+    let mut session = Session::new(Some(addr), local_addr, listener);
+
+    if implicit_tls {
+        match smtps_server(
+            socket,
+            &mut handler,
+            &mut session,
+            &config,
+            shutdown,
+            &RustlsAcceptor,
+        )
+        .await
+        {
+            Ok(_) => println!("SMTPS Server done"),
+            Err(e) => println!("SMTPS Top level error: {:?}", e),
         }
+        return Ok(());
+    }
+
+    match smtp_session(
+        socket,
+        &mut handler,
+        &mut session,
+        &config,
+        shutdown,
+        &RustlsAcceptor,
+    )
+    .await
+    {
+        Ok(_) => println!("Server done"),
         Err(e) => println!("Top level error: {:?}", e),
     }
 
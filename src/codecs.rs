@@ -2,45 +2,121 @@ use std::convert::TryInto;
 use std::error::Error;
 use std::fmt::{Display, Write};
 
-use bytes::BytesMut;
+use bytes::{Buf, BytesMut};
 use tokio_util::codec::{Decoder, Encoder};
 
-use crate::Reply;
+use crate::{EhloKeywords, Reply};
 
-const DEFAULT_LINE_LENGTH: usize = 2048;
+/// Command line limit from RFC 5321 4.5.3.1.4, including CRLF.
+const DEFAULT_COMMAND_LENGTH: usize = 512;
+/// Text line limit from RFC 5321 4.5.3.1.6, including CRLF.
+const DEFAULT_TEXT_LENGTH: usize = 1000;
 const DEFAULT_MAX_CHUNK_SIZE: u64 = 1024 * 1024;
+/// AUTH command line limit from RFC 4954 section 4, including CRLF.
+const AUTH_COMMAND_LENGTH: usize = 12288;
 
 #[derive(Clone, Debug)]
 pub struct LineCodec {
-    max_length: usize,
+    max_command_length: usize,
+    /// `max_command_length` raised for the advertised extensions.
+    command_length: usize,
+    /// AUTH is advertised, its command lines may be longer.
+    auth: bool,
+    /// Reading a SASL response line.
+    sasl: bool,
+    max_text_length: usize,
     /// Max chunk that is buffered at once. If a BDAT is larger than
     /// this, it will be split into chunks of this size.
     max_chunk_size: u64,
-    valid: bool,
+    /// Reading DATA text lines rather than commands.
+    data: bool,
     state: State,
 }
 
+/// Builder for a [`LineCodec`] with custom limits.
+///
+/// Lengths include the CRLF. Limits below the RFC 5321 minimums
+/// break conforming clients.
+#[derive(Clone, Debug, Default)]
+pub struct LineCodecBuilder {
+    max_command_length: Option<usize>,
+    max_text_length: Option<usize>,
+    max_chunk_size: Option<u64>,
+}
+
+impl LineCodecBuilder {
+    /// Longest command line, 512 by default. Raised after EHLO for
+    /// extensions that allow longer commands, such as AUTH.
+    pub fn max_command_length(mut self, length: usize) -> Self {
+        self.max_command_length = Some(length);
+        self
+    }
+
+    /// Longest DATA text line, 1000 by default.
+    pub fn max_text_length(mut self, length: usize) -> Self {
+        self.max_text_length = Some(length);
+        self
+    }
+
+    /// Largest piece of a BDAT chunk buffered at once, 1 MiB by
+    /// default. Larger chunks reach the handler in several pieces.
+    pub fn max_chunk_size(mut self, size: u64) -> Self {
+        self.max_chunk_size = Some(size);
+        self
+    }
+
+    pub fn build(self) -> LineCodec {
+        LineCodec::new(
+            self.max_command_length,
+            self.max_text_length,
+            self.max_chunk_size,
+        )
+    }
+}
+
 #[derive(Debug)]
 pub enum LineError {
     LineTooLong,
     IO(std::io::Error),
     ChunkingDone,
     DataAbort,
+    MessageTooLarge,
+    BareNewline,
 }
 
 #[derive(Clone, Debug)]
 enum State {
-    Text { next_index: usize },
+    Text {
+        next_index: usize,
+    },
     Chunk(u64),
+    /// Skipping the rest of an over-long line.
+    Discard,
 }
 
 impl LineCodec {
-    fn new(max_length: Option<usize>, max_chunk_size: Option<u64>) -> Self {
+    pub fn builder() -> LineCodecBuilder {
+        LineCodecBuilder::default()
+    }
+
+    fn new(
+        max_command_length: Option<usize>,
+        max_text_length: Option<usize>,
+        max_chunk_size: Option<u64>,
+    ) -> Self {
+        // Room for at least an end of data line, and chunks that
+        // make progress.
+        let max_command_length = max_command_length.unwrap_or(DEFAULT_COMMAND_LENGTH).max(3);
+
         Self {
-            max_length: max_length.unwrap_or(DEFAULT_LINE_LENGTH),
-            max_chunk_size: max_chunk_size.unwrap_or(DEFAULT_MAX_CHUNK_SIZE),
+            max_command_length,
+            command_length: max_command_length,
+            auth: false,
+            sasl: false,
+            max_text_length: max_text_length.unwrap_or(DEFAULT_TEXT_LENGTH).max(3),
+            max_chunk_size: max_chunk_size.unwrap_or(DEFAULT_MAX_CHUNK_SIZE).max(1),
+            data: false,
             state: State::Text { next_index: 0 },
-            valid: true,
         }
     }
 
@@ -49,7 +125,14 @@ impl LineCodec {
         buf: &mut BytesMut,
         next_index: usize,
     ) -> Result<Option<BytesMut>, LineError> {
-        let read_to = std::cmp::min(self.max_length.saturating_add(1), buf.len());
+        let max_length = if self.data {
+            self.max_text_length
+        } else if self.sasl || self.auth && is_auth_command(buf) {
+            std::cmp::max(self.command_length, AUTH_COMMAND_LENGTH)
+        } else {
+            self.command_length
+        };
+        let read_to = std::cmp::min(max_length, buf.len());
 
         let crlf_offset = buf[next_index..read_to]
             .windows(2)
@@ -59,13 +142,20 @@ impl LineCodec {
         match crlf_offset {
             Some(offset) => {
                 self.state = State::Text { next_index: 0 };
+                self.sasl = false;
+                let line = buf.split_to(offset);
 
-                Ok(Some(buf.split_to(offset)))
+                // The end of data is never dot-stuffed.
+                if self.data && line.as_ref() == b".\r\n" {
+                    self.data = false;
+                }
+
+                Ok(Some(line))
             }
             None => {
-                if buf.len() > self.max_length {
-                    self.valid = false;
-                    Err(LineError::LineTooLong)
+                if buf.len() >= max_length {
+                    self.state = State::Discard;
+                    self.decode_discard(buf)
                 } else {
                     self.state = State::Text {
                         next_index: buf.len().saturating_sub(1),
@@ -76,6 +166,25 @@ impl LineCodec {
         }
     }
 
+    /// Drop input up to the next CRLF, then report the over-long line
+    /// in its place.
+    fn decode_discard(&mut self, buf: &mut BytesMut) -> Result<Option<BytesMut>, LineError> {
+        match buf.windows(2).position(|x| x == b"\r\n") {
+            Some(index) => {
+                buf.advance(index + 2);
+                self.state = State::Text { next_index: 0 };
+                self.sasl = false;
+                Err(LineError::LineTooLong)
+            }
+            None => {
+                // Keep a trailing CR that may start the CRLF.
+                let keep = if buf.ends_with(b"\r") { 1 } else { 0 };
+                buf.advance(buf.len() - keep);
+                Ok(None)
+            }
+        }
+    }
+
     fn decode_binary(
         &mut self,
         buf: &mut BytesMut,
@@ -91,7 +200,7 @@ impl LineCodec {
         let buf_len: u64 = buf.len().try_into().unwrap();
 
         if buf_len >= wanted_chunk {
-            let chunk_size: usize = wanted_chunk.try_into().unwrap_or(std::usize::MAX);
+            let chunk_size: usize = wanted_chunk.try_into().unwrap_or(usize::MAX);
             let chunk_size_u64: u64 = chunk_size.try_into().unwrap();
 
             self.state = State::Chunk(bytes_remaining - chunk_size_u64);
@@ -101,17 +210,63 @@ impl LineCodec {
         }
     }
 
-    pub(crate) fn chunking_mode(&mut self, chunk_size: u64) {
-        self.state = match self.state {
-            State::Text { .. } => State::Chunk(chunk_size),
-            State::Chunk(..) => panic!("Invalid chunking state"),
+    /// Read a BDAT chunk of `chunk_size` bytes. Only possible between
+    /// command lines.
+    pub(crate) fn chunking_mode(&mut self, chunk_size: u64) -> Result<(), LineError> {
+        match self.state {
+            State::Text { .. } => {
+                self.state = State::Chunk(chunk_size);
+                Ok(())
+            }
+            State::Chunk(..) => Err(LineError::DataAbort),
+            State::Discard => Err(LineError::LineTooLong),
+        }
+    }
+
+    /// Allow the longer command lines of the advertised `extensions`:
+    /// SIZE (RFC 1870), DSN (RFC 3461) and SMTPUTF8 (RFC 6531) add
+    /// to the MAIL and RCPT limit, AUTH commands may take 12288
+    /// octets.
+    pub(crate) fn set_extensions(&mut self, extensions: &EhloKeywords) {
+        let has = |keyword| extensions.contains_key(keyword);
+        let mut mail = 0;
+        let mut rcpt = 0;
+
+        if has("SIZE") {
+            mail += 26;
         }
+        if has("SMTPUTF8") {
+            mail += 10;
+        }
+        if has("DSN") {
+            mail += 100;
+            rcpt += 500;
+        }
+
+        self.command_length = self.max_command_length + std::cmp::max(mail, rcpt);
+        self.auth = has("AUTH");
+    }
+
+    /// Read the next line as a SASL response, as long as an AUTH
+    /// command.
+    pub(crate) fn sasl_mode(&mut self) {
+        self.sasl = true;
+    }
+
+    /// Read DATA text lines until the end of data line.
+    pub(crate) fn data_mode(&mut self) {
+        self.data = true;
     }
 }
 
+fn is_auth_command(buf: &[u8]) -> bool {
+    buf.get(..5)
+        .is_some_and(|verb| verb.eq_ignore_ascii_case(b"AUTH "))
+}
+
 impl Default for LineCodec {
     fn default() -> Self {
-        Self::new(None, None)
+        Self::new(None, None, None)
     }
 }
 
@@ -120,13 +275,10 @@ impl Decoder for LineCodec {
     type Item = BytesMut;
 
     fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        if !self.valid {
-            return Err(LineError::LineTooLong);
-        }
-
         match self.state {
             State::Text { next_index } => self.decode_text(buf, next_index),
             State::Chunk(remaining) => self.decode_binary(buf, remaining),
+            State::Discard => self.decode_discard(buf),
         }
     }
 }
@@ -153,3 +305,203 @@ impl Display for LineError {
         write!(fmt, "{:?}", self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(codec: &mut LineCodec, buf: &mut BytesMut) -> Result<Option<Vec<u8>>, LineError> {
+        codec.decode(buf).map(|line| line.map(|line| line.to_vec()))
+    }
+
+    #[test]
+    fn discards_long_line() {
+        let mut codec = LineCodec::builder().max_command_length(10).build();
+        let mut buf = BytesMut::from(&b"NOOP 0123456789"[..]);
+
+        assert_eq!(decode(&mut codec, &mut buf).unwrap(), None);
+        assert!(buf.is_empty());
+
+        // The CRLF may be split across reads.
+        buf.extend_from_slice(b"abc\r");
+        assert_eq!(decode(&mut codec, &mut buf).unwrap(), None);
+        assert_eq!(&buf[..], b"\r");
+
+        buf.extend_from_slice(b"\nQUIT\r\n");
+        assert!(matches!(
+            decode(&mut codec, &mut buf),
+            Err(LineError::LineTooLong)
+        ));
+        assert_eq!(
+            decode(&mut codec, &mut buf).unwrap().as_deref(),
+            Some(&b"QUIT\r\n"[..])
+        );
+    }
+
+    #[test]
+    fn separate_text_limit() {
+        let mut codec = LineCodec::builder()
+            .max_command_length(10)
+            .max_text_length(20)
+            .build();
+        let mut buf = BytesMut::from(&b"0123456789abcdef\r\n.\r\n0123456789abcdef\r\nQUIT\r\n"[..]);
+
+        codec.data_mode();
+        assert_eq!(
+            decode(&mut codec, &mut buf).unwrap().as_deref(),
+            Some(&b"0123456789abcdef\r\n"[..])
+        );
+        assert_eq!(
+            decode(&mut codec, &mut buf).unwrap().as_deref(),
+            Some(&b".\r\n"[..])
+        );
+        // Back to commands after the end of data.
+        assert!(matches!(
+            decode(&mut codec, &mut buf),
+            Err(LineError::LineTooLong)
+        ));
+        assert_eq!(
+            decode(&mut codec, &mut buf).unwrap().as_deref(),
+            Some(&b"QUIT\r\n"[..])
+        );
+    }
+
+    #[test]
+    fn long_text_line_keeps_data_mode() {
+        let mut codec = LineCodec::builder().max_text_length(5).build();
+        let mut buf = BytesMut::from(&b"0123456789\r\n..\r\n.\r\n"[..]);
+
+        codec.data_mode();
+        assert!(matches!(
+            decode(&mut codec, &mut buf),
+            Err(LineError::LineTooLong)
+        ));
+        assert_eq!(
+            decode(&mut codec, &mut buf).unwrap().as_deref(),
+            Some(&b"..\r\n"[..])
+        );
+        assert_eq!(
+            decode(&mut codec, &mut buf).unwrap().as_deref(),
+            Some(&b".\r\n"[..])
+        );
+    }
+
+    #[test]
+    fn chunking_only_between_lines() {
+        let mut codec = LineCodec::builder().max_command_length(5).build();
+        let mut buf = BytesMut::from(&b"0123456789"[..]);
+
+        assert_eq!(decode(&mut codec, &mut buf).unwrap(), None);
+        assert!(matches!(
+            codec.chunking_mode(3),
+            Err(LineError::LineTooLong)
+        ));
+
+        let mut codec = LineCodec::default();
+        codec.chunking_mode(3).unwrap();
+        assert!(matches!(codec.chunking_mode(3), Err(LineError::DataAbort)));
+    }
+
+    #[test]
+    fn extension_command_length() {
+        let mut codec = LineCodec::default();
+        let mut extensions = EhloKeywords::new();
+
+        codec.set_extensions(&extensions);
+        assert_eq!(codec.command_length, 512);
+
+        extensions.insert("SIZE".into(), Some("1000".into()));
+        extensions.insert("SMTPUTF8".into(), None);
+        codec.set_extensions(&extensions);
+        assert_eq!(codec.command_length, 512 + 36);
+
+        extensions.insert("DSN".into(), None);
+        codec.set_extensions(&extensions);
+        assert_eq!(codec.command_length, 512 + 500);
+
+        extensions.insert("AUTH".into(), Some("PLAIN".into()));
+        codec.set_extensions(&extensions);
+        assert_eq!(codec.command_length, 512 + 500);
+        assert!(codec.auth);
+
+        codec.set_extensions(&EhloKeywords::new());
+        assert_eq!(codec.command_length, 512);
+        assert!(!codec.auth);
+    }
+
+    #[test]
+    fn auth_command_length() {
+        let mut codec = LineCodec::default();
+        let mut extensions = EhloKeywords::new();
+        extensions.insert("AUTH".into(), Some("PLAIN".into()));
+        codec.set_extensions(&extensions);
+
+        let long = "x".repeat(2000);
+        let mut buf = BytesMut::from(format!("auth PLAIN {}\r\n", long).as_bytes());
+        assert!(decode(&mut codec, &mut buf).unwrap().is_some());
+
+        let mut buf = BytesMut::from(format!("NOOP {}\r\n", long).as_bytes());
+        assert!(matches!(
+            decode(&mut codec, &mut buf),
+            Err(LineError::LineTooLong)
+        ));
+
+        // Only the SASL response following the challenge.
+        let mut buf = BytesMut::from(format!("{0}\r\n{0}\r\n", long).as_bytes());
+        codec.sasl_mode();
+        assert!(decode(&mut codec, &mut buf).unwrap().is_some());
+        assert!(matches!(
+            decode(&mut codec, &mut buf),
+            Err(LineError::LineTooLong)
+        ));
+    }
+
+    #[test]
+    fn builder_limits() {
+        let codec = LineCodec::builder()
+            .max_command_length(100)
+            .max_text_length(200)
+            .max_chunk_size(300)
+            .build();
+        assert_eq!(codec.max_command_length, 100);
+        assert_eq!(codec.command_length, 100);
+        assert_eq!(codec.max_text_length, 200);
+        assert_eq!(codec.max_chunk_size, 300);
+
+        let codec = LineCodec::builder().build();
+        assert_eq!(codec.max_command_length, DEFAULT_COMMAND_LENGTH);
+        assert_eq!(codec.max_text_length, DEFAULT_TEXT_LENGTH);
+        assert_eq!(codec.max_chunk_size, DEFAULT_MAX_CHUNK_SIZE);
+
+        let codec = LineCodec::builder()
+            .max_command_length(0)
+            .max_text_length(0)
+            .max_chunk_size(0)
+            .build();
+        assert_eq!(codec.max_command_length, 3);
+        assert_eq!(codec.max_text_length, 3);
+        assert_eq!(codec.max_chunk_size, 1);
+    }
+
+    #[test]
+    fn splits_large_chunks() {
+        let mut codec = LineCodec::builder().max_chunk_size(4).build();
+        let mut buf = BytesMut::from(&b"0123456789QUIT\r\n"[..]);
+
+        codec.chunking_mode(10).unwrap();
+        for piece in [&b"0123"[..], b"4567", b"89"] {
+            assert_eq!(
+                decode(&mut codec, &mut buf).unwrap().as_deref(),
+                Some(piece)
+            );
+        }
+        assert!(matches!(
+            decode(&mut codec, &mut buf),
+            Err(LineError::ChunkingDone)
+        ));
+        assert_eq!(
+            decode(&mut codec, &mut buf).unwrap().as_deref(),
+            Some(&b"QUIT\r\n"[..])
+        );
+    }
+}
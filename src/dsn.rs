@@ -0,0 +1,71 @@
+use rustyknife::rfc5321::ForwardPath;
+
+use crate::{MailParams, OriginalRecipient, RcptParams, Ret};
+
+/// Validated DSN request (RFC 3461) for a mail transaction.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DsnRequest {
+    pub ret: Option<Ret>,
+    pub envid: Option<String>,
+    /// One entry per accepted recipient, in order.
+    pub recipients: Vec<DsnRecipient>,
+}
+
+/// DSN request for a single recipient.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DsnRecipient {
+    pub path: ForwardPath,
+    /// `None` when NOTIFY was not given, the MTA default applies.
+    pub notify: Option<DsnNotify>,
+    pub orcpt: Option<OriginalRecipient>,
+}
+
+/// Validated NOTIFY parameter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DsnNotify {
+    Never,
+    On {
+        success: bool,
+        failure: bool,
+        delay: bool,
+    },
+}
+
+impl DsnRequest {
+    /// Start a request from the MAIL parameters.
+    pub fn from_mail(params: &MailParams) -> Self {
+        Self {
+            ret: params.ret,
+            envid: params.envid.clone(),
+            recipients: Vec::new(),
+        }
+    }
+
+    /// DSN request for `path` from its RCPT parameters.
+    ///
+    /// The returned entry is added with [`DsnRequest::add_recipient`]
+    /// once the recipient is accepted.
+    pub fn recipient(path: &ForwardPath, params: &RcptParams) -> DsnRecipient {
+        let notify = params.notify.as_ref().map(|notify| {
+            if notify.never {
+                DsnNotify::Never
+            } else {
+                DsnNotify::On {
+                    success: notify.success,
+                    failure: notify.failure,
+                    delay: notify.delay,
+                }
+            }
+        });
+
+        DsnRecipient {
+            path: path.clone(),
+            notify,
+            orcpt: params.orcpt.clone(),
+        }
+    }
+
+    pub fn add_recipient(&mut self, recipient: DsnRecipient) {
+        self.recipients.push(recipient);
+    }
+}
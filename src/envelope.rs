@@ -0,0 +1,38 @@
+use rustyknife::rfc5321::{ForwardPath, ReversePath};
+
+use crate::{DsnRequest, MailParams, RcptParams, Xforward};
+
+/// Mail transaction envelope collected by the server.
+///
+/// Handed to the body callbacks and to
+/// [`Handler::transaction_end`](crate::Handler::transaction_end).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Envelope {
+    /// Reverse path from the accepted MAIL command.
+    pub reverse_path: Option<ReversePath>,
+    pub mail_params: MailParams,
+    /// Recipients that got a positive reply, in order.
+    pub recipients: Vec<EnvelopeRecipient>,
+    /// Validated DSN request, `None` unless DSN was advertised.
+    pub dsn: Option<DsnRequest>,
+    /// Attributes from XFORWARD commands sent before MAIL.
+    pub xforward: Xforward,
+}
+
+/// Accepted recipient and its RCPT parameters.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnvelopeRecipient {
+    pub path: ForwardPath,
+    pub params: RcptParams,
+}
+
+/// How a mail transaction ended.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransactionOutcome {
+    /// The final DATA or BDAT reply was positive, for at least one
+    /// recipient with LMTP.
+    Committed,
+    /// Ended by RSET, a new greeting, a failed message or the end of
+    /// the session.
+    Aborted,
+}
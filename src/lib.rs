@@ -1,11 +1,43 @@
 #![warn(rust_2018_idioms)]
 
+mod auth;
 mod codecs;
+mod dsn;
+mod envelope;
+mod limits;
+mod newline;
+mod params;
+mod pipelining;
+mod proxy;
 mod reply;
+mod response;
+mod scram;
 mod server;
+mod session;
 mod syntax;
+mod tarpit;
+mod timeout;
+mod tls;
+mod xclient;
+mod xforward;
 
-pub use codecs::{LineCodec, LineError};
+pub use auth::*;
+pub use codecs::{LineCodec, LineCodecBuilder, LineError};
+pub use dsn::*;
+pub use envelope::*;
+pub use limits::{ErrorLimit, Limits};
+pub use newline::{BareNewline, BareNewlinePolicy};
+pub use params::*;
+pub use pipelining::*;
+pub use proxy::*;
 pub use reply::*;
+pub use response::*;
+pub use scram::*;
 pub use server::*;
+pub use session::*;
 pub use syntax::*;
+pub use tarpit::*;
+pub use timeout::Timeouts;
+pub use tls::*;
+pub use xclient::*;
+pub use xforward::*;
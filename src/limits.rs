@@ -0,0 +1,114 @@
+use std::time::Duration;
+
+/// Soft and hard thresholds on a count of client errors.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ErrorLimit {
+    /// Errors past this many are answered after
+    /// [`Limits::soft_delay`].
+    pub soft: Option<u64>,
+    /// Reaching this many errors closes the connection with 421.
+    pub hard: Option<u64>,
+}
+
+impl ErrorLimit {
+    pub(crate) fn hard_reached(&self, count: u64) -> bool {
+        self.hard.is_some_and(|hard| count >= hard)
+    }
+
+    pub(crate) fn soft_exceeded(&self, count: u64) -> bool {
+        self.soft.is_some_and(|soft| count > soft)
+    }
+}
+
+/// Limits against abusive sessions, similar to Postfix's
+/// `smtpd_soft_error_limit`, `smtpd_hard_error_limit` and
+/// `smtpd_recipient_limit`. Nothing is limited by default.
+///
+/// Error counts are kept for the whole session, see
+/// [`Session`](crate::Session).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Limits {
+    /// Malformed commands and over-long lines.
+    pub syntax_errors: ErrorLimit,
+    /// Unrecognized or unimplemented commands.
+    pub unknown_commands: ErrorLimit,
+    /// RCPT commands answered with an error.
+    pub rejected_recipients: ErrorLimit,
+    /// Delay once a soft limit is exceeded.
+    pub soft_delay: Duration,
+    /// Further RCPT commands in a transaction get 452 4.5.3. RFC 5321
+    /// 4.5.3.1.8 requires accepting at least 100.
+    pub max_recipients: Option<usize>,
+    /// MAIL past this many transactions closes the connection with
+    /// 421.
+    pub max_transactions: Option<u64>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            syntax_errors: ErrorLimit::default(),
+            unknown_commands: ErrorLimit::default(),
+            rejected_recipients: ErrorLimit::default(),
+            soft_delay: Duration::from_secs(1),
+            max_recipients: None,
+            max_transactions: None,
+        }
+    }
+}
+
+/// Error counters checked against [`Limits`].
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum ErrorCount {
+    SyntaxErrors,
+    UnknownCommands,
+    RejectedRecipients,
+}
+
+/// Command verbs the server knows, to tell unknown commands from
+/// malformed ones.
+const KNOWN_VERBS: &[&str] = &[
+    "EHLO", "HELO", "LHLO", "MAIL", "RCPT", "DATA", "RSET", "NOOP", "QUIT", "VRFY", "EXPN", "HELP",
+    "STARTTLS", "BDAT", "AUTH", "XFORWARD", "XCLIENT",
+];
+
+/// Whether `line` starts with a known command verb.
+pub(crate) fn known_verb(line: &[u8]) -> bool {
+    let verb = line
+        .split(|b| *b == b' ' || *b == b'\r' || *b == b'\n')
+        .next()
+        .unwrap_or_default();
+
+    KNOWN_VERBS
+        .iter()
+        .any(|known| verb.eq_ignore_ascii_case(known.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_limit() {
+        let limit = ErrorLimit {
+            soft: Some(2),
+            hard: Some(4),
+        };
+
+        assert!(!limit.soft_exceeded(2));
+        assert!(limit.soft_exceeded(3));
+        assert!(!limit.hard_reached(3));
+        assert!(limit.hard_reached(4));
+        assert!(!ErrorLimit::default().soft_exceeded(u64::MAX));
+        assert!(!ErrorLimit::default().hard_reached(u64::MAX));
+    }
+
+    #[test]
+    fn known_verbs() {
+        assert!(known_verb(b"MAIL FROM:broken\r\n"));
+        assert!(known_verb(b"noop\r\n"));
+        assert!(known_verb(b"STARTTLS"));
+        assert!(!known_verb(b"MAILFROM:<a@example.org>\r\n"));
+        assert!(!known_verb(b"\r\n"));
+    }
+}
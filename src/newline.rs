@@ -0,0 +1,140 @@
+use bytes::BytesMut;
+
+/// At most this many bare newlines are recorded per message.
+pub(crate) const MAX_BARE_NEWLINE_REPORTS: usize = 100;
+
+/// Handling of bare CR and LF in DATA bodies.
+///
+/// Line endings other than CRLF may be read differently by the next
+/// MTA, which allows smuggling a second message past it with an end
+/// of data such as `\n.\n`. BDAT bodies are not affected.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BareNewlinePolicy {
+    /// Discard the message with 550 5.6.0.
+    Reject,
+    /// Replace each bare CR or LF with CRLF.
+    Normalize,
+    /// Keep the body unchanged.
+    PassThrough,
+}
+
+/// A bare CR or LF found in a DATA body.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BareNewline {
+    /// The offending byte, `b'\r'` or `b'\n'`.
+    pub byte: u8,
+    /// Body line it was found in, starting at 1.
+    pub line: u64,
+    /// Part of an end of data sequence using a bare line ending, such
+    /// as `\n.\n` or `\r\n.\r`.
+    pub end_of_data: bool,
+}
+
+/// Find the bare CR and LF in a dot-stuffed, CRLF terminated line.
+pub(crate) fn find_bare_newlines(line: &[u8], number: u64) -> Vec<BareNewline> {
+    let content = line.strip_suffix(b"\r\n").unwrap_or(line);
+
+    content
+        .iter()
+        .enumerate()
+        .filter(|(_, byte)| is_newline(**byte))
+        .map(|(index, byte)| BareNewline {
+            byte: *byte,
+            line: number,
+            end_of_data: is_end_of_data(content, index),
+        })
+        .collect()
+}
+
+/// Split a CRLF terminated line at its bare CR and LF, ending each
+/// part with CRLF.
+pub(crate) fn normalize_newlines(line: &[u8]) -> Vec<BytesMut> {
+    let (content, end) = match line.strip_suffix(b"\r\n") {
+        Some(content) => (content, &b"\r\n"[..]),
+        None => (line, &b""[..]),
+    };
+    let mut lines: Vec<BytesMut> = content
+        .split(|byte| is_newline(*byte))
+        .map(|part| {
+            let mut out = BytesMut::with_capacity(part.len() + 2);
+            out.extend_from_slice(part);
+            out.extend_from_slice(b"\r\n");
+            out
+        })
+        .collect();
+
+    if let Some(last) = lines.last_mut() {
+        last.truncate(last.len() - 2);
+        last.extend_from_slice(end);
+        if last.is_empty() {
+            lines.pop();
+        }
+    }
+
+    lines
+}
+
+fn is_newline(byte: u8) -> bool {
+    byte == b'\r' || byte == b'\n'
+}
+
+/// Whether the newline at `index` ends or starts a lone "." line.
+fn is_end_of_data(content: &[u8], index: usize) -> bool {
+    let dot_after = content.get(index + 1) == Some(&b'.')
+        && content.get(index + 2).is_none_or(|b| is_newline(*b));
+    let dot_before =
+        index >= 1 && content[index - 1] == b'.' && (index == 1 || is_newline(content[index - 2]));
+
+    dot_after || dot_before
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bare(byte: u8, end_of_data: bool) -> BareNewline {
+        BareNewline {
+            byte,
+            line: 3,
+            end_of_data,
+        }
+    }
+
+    fn normalized(line: &[u8]) -> Vec<Vec<u8>> {
+        normalize_newlines(line)
+            .into_iter()
+            .map(|line| line.to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn finds_bare_newlines() {
+        assert!(find_bare_newlines(b"hello\r\n", 3).is_empty());
+        assert_eq!(
+            find_bare_newlines(b"a\nb\rc\r\n", 3),
+            [bare(b'\n', false), bare(b'\r', false)]
+        );
+        assert_eq!(
+            find_bare_newlines(b"hello\n.\nworld\r\n", 3),
+            [bare(b'\n', true), bare(b'\n', true)]
+        );
+        assert_eq!(find_bare_newlines(b"hello\r.\r\r\n", 3).len(), 2);
+        assert!(find_bare_newlines(b"hello\r.\r\r\n", 3)
+            .iter()
+            .all(|b| b.end_of_data && b.byte == b'\r'));
+        assert_eq!(find_bare_newlines(b".\nworld\r\n", 3), [bare(b'\n', true)]);
+        assert_eq!(find_bare_newlines(b"a\n.b\r\n", 3), [bare(b'\n', false)]);
+    }
+
+    #[test]
+    fn normalizes_into_lines() {
+        assert_eq!(normalized(b"a\nb\r\n"), [&b"a\r\n"[..], b"b\r\n"]);
+        assert_eq!(
+            normalized(b"hello\n.\nsmuggled\r\n"),
+            [&b"hello\r\n"[..], b".\r\n", b"smuggled\r\n"]
+        );
+        assert_eq!(normalized(b"a\n\r\n"), [&b"a\r\n"[..], b"\r\n"]);
+        assert_eq!(normalized(b"a\rb"), [&b"a\r\n"[..], b"b"]);
+        assert_eq!(normalized(b"a\n"), [b"a\r\n"]);
+    }
+}
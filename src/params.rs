@@ -0,0 +1,278 @@
+use rustyknife::behaviour::Intl;
+use rustyknife::rfc3461::{dsn_mail_params, dsn_notify, orcpt_address, DSNRet};
+use rustyknife::rfc5321::{validate_address, Param};
+
+use crate::{xtext_decode, EhloKeywords, EnhancedCode, Reply};
+
+/// Maximum ORCPT length from RFC 3461 section 4.2.
+const MAX_ORCPT_LENGTH: usize = 500;
+
+/// Message body type from the BODY parameter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Body {
+    SevenBit,
+    EightBitMime,
+    /// RFC 3030, requires CHUNKING.
+    BinaryMime,
+}
+
+/// DSN return type from the RET parameter (RFC 3461).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ret {
+    Full,
+    Hdrs,
+}
+
+/// Decoded ESMTP parameters of a MAIL command.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MailParams {
+    pub body: Option<Body>,
+    /// Declared message size (RFC 1870).
+    pub size: Option<u64>,
+    /// DSN return type (RFC 3461).
+    pub ret: Option<Ret>,
+    /// xtext decoded DSN envelope identifier (RFC 3461).
+    pub envid: Option<String>,
+    /// xtext decoded AUTH identity (RFC 4954), `"<>"` when the
+    /// identity is unknown.
+    pub auth: Option<String>,
+    /// SMTPUTF8 was requested (RFC 6531).
+    pub smtputf8: bool,
+    /// Parameters of other extensions advertised by the handler,
+    /// named after the extension such as `MT-PRIORITY`. Keywords are
+    /// uppercase, values are not decoded.
+    pub extra: Vec<(String, Option<String>)>,
+}
+
+/// NOTIFY keywords (RFC 3461). `never` is only set on its own.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Notify {
+    pub never: bool,
+    pub success: bool,
+    pub failure: bool,
+    pub delay: bool,
+}
+
+/// Original recipient from the ORCPT parameter (RFC 3461).
+#[derive(Clone, Debug, PartialEq)]
+pub struct OriginalRecipient {
+    /// Address type such as `"rfc822"`.
+    pub addr_type: String,
+    /// xtext decoded address.
+    pub address: String,
+}
+
+/// Decoded ESMTP parameters of a RCPT command.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RcptParams {
+    pub notify: Option<Notify>,
+    pub orcpt: Option<OriginalRecipient>,
+    /// Parameters of other advertised extensions, like
+    /// [`MailParams::extra`].
+    pub extra: Vec<(String, Option<String>)>,
+}
+
+impl MailParams {
+    /// Decode the parameters of a MAIL command.
+    ///
+    /// Parameters must belong to an extension in `extensions`, the
+    /// keywords advertised in the EHLO reply.
+    pub fn parse(params: Vec<Param>, extensions: &EhloKeywords) -> Result<Self, Reply> {
+        let mut out = Self::default();
+        let mut seen = Vec::new();
+
+        for Param(keyword, value) in params {
+            let keyword = keyword.to_ascii_uppercase();
+            let value = value.as_deref();
+
+            match keyword.as_str() {
+                "BODY" => {
+                    let body = match value.map(str::to_ascii_uppercase).as_deref() {
+                        Some("7BIT") => Body::SevenBit,
+                        Some("8BITMIME") => Body::EightBitMime,
+                        Some("BINARYMIME") => Body::BinaryMime,
+                        _ => return Err(invalid_value(&keyword)),
+                    };
+                    let extension = match body {
+                        Body::BinaryMime => "BINARYMIME",
+                        _ => "8BITMIME",
+                    };
+                    check_param(&mut seen, &keyword, extension, extensions)?;
+                    out.body = Some(body);
+                }
+                "SIZE" => {
+                    check_param(&mut seen, &keyword, "SIZE", extensions)?;
+                    out.size = Some(
+                        value
+                            .and_then(|v| v.parse().ok())
+                            .ok_or_else(|| invalid_value(&keyword))?,
+                    );
+                }
+                "RET" | "ENVID" => {
+                    check_param(&mut seen, &keyword, "DSN", extensions)?;
+                    let (dsn, _) = dsn_mail_params(&[(&keyword, value)])
+                        .map_err(|_| invalid_value(&keyword))?;
+
+                    if let Some(ret) = dsn.ret {
+                        out.ret = Some(match ret {
+                            DSNRet::Full => Ret::Full,
+                            DSNRet::Hdrs => Ret::Hdrs,
+                        });
+                    }
+                    if let Some(envid) = dsn.envid {
+                        out.envid = Some(envid);
+                    }
+                }
+                "AUTH" => {
+                    check_param(&mut seen, &keyword, "AUTH", extensions)?;
+                    out.auth = Some(
+                        value
+                            .and_then(xtext_decode)
+                            .ok_or_else(|| invalid_value(&keyword))?,
+                    );
+                }
+                "SMTPUTF8" => {
+                    check_param(&mut seen, &keyword, "SMTPUTF8", extensions)?;
+                    if value.is_some() {
+                        return Err(invalid_value(&keyword));
+                    }
+                    out.smtputf8 = true;
+                }
+                _ => out
+                    .extra
+                    .push(extra_param(&mut seen, keyword, value, extensions)?),
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+impl RcptParams {
+    /// Decode the parameters of a RCPT command.
+    ///
+    /// Parameters must belong to an extension in `extensions`, the
+    /// keywords advertised in the EHLO reply.
+    pub fn parse(params: Vec<Param>, extensions: &EhloKeywords) -> Result<Self, Reply> {
+        let mut out = Self::default();
+        let mut seen = Vec::new();
+
+        for Param(keyword, value) in params {
+            let keyword = keyword.to_ascii_uppercase();
+            let value = value.as_deref();
+
+            match keyword.as_str() {
+                "NOTIFY" => {
+                    check_param(&mut seen, &keyword, "DSN", extensions)?;
+                    let value = value.ok_or_else(|| invalid_value(&keyword))?;
+
+                    // dsn_notify fails on NEVER combinations like on any
+                    // syntax error, they get a reply of their own.
+                    let mut items = value.split(',');
+                    if items.clone().count() > 1 && items.any(|i| i.eq_ignore_ascii_case("NEVER")) {
+                        return Err(Reply::new(
+                            501,
+                            Some(EnhancedCode(5, 5, 4)),
+                            "NOTIFY=NEVER cannot be combined with other values",
+                        ));
+                    }
+                    let notify = match dsn_notify(value) {
+                        Ok(("", notify)) => notify,
+                        _ => return Err(invalid_value(&keyword)),
+                    };
+
+                    // NEVER is the only way to get no flag set.
+                    out.notify = Some(Notify {
+                        never: !(notify.on_success || notify.on_failure || notify.delay),
+                        success: notify.on_success,
+                        failure: notify.on_failure,
+                        delay: notify.delay,
+                    });
+                }
+                "ORCPT" => {
+                    check_param(&mut seen, &keyword, "DSN", extensions)?;
+                    let value = value.ok_or_else(|| invalid_value(&keyword))?;
+                    if value.len() > MAX_ORCPT_LENGTH {
+                        return Err(invalid_value(&keyword));
+                    }
+
+                    let orcpt = match orcpt_address(value.as_bytes()) {
+                        Ok((b"", (addr_type, address))) => OriginalRecipient {
+                            addr_type: addr_type.into(),
+                            address: address.into(),
+                        },
+                        _ => return Err(invalid_value(&keyword)),
+                    };
+                    if orcpt.addr_type.eq_ignore_ascii_case("rfc822")
+                        && !validate_address::<Intl>(orcpt.address.as_bytes())
+                    {
+                        return Err(invalid_value(&keyword));
+                    }
+                    out.orcpt = Some(orcpt);
+                }
+                _ => out
+                    .extra
+                    .push(extra_param(&mut seen, keyword, value, extensions)?),
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+fn check_param(
+    seen: &mut Vec<String>,
+    keyword: &str,
+    extension: &str,
+    extensions: &EhloKeywords,
+) -> Result<(), Reply> {
+    if !extensions.contains_key(extension) {
+        return Err(Reply::new(
+            555,
+            Some(EnhancedCode(5, 5, 4)),
+            format!("{} parameter requires {}", keyword, extension),
+        ));
+    }
+    if seen.iter().any(|k| k == keyword) {
+        return Err(Reply::new(
+            555,
+            Some(EnhancedCode(5, 5, 4)),
+            format!("Duplicate {} parameter", keyword),
+        ));
+    }
+    seen.push(keyword.into());
+
+    Ok(())
+}
+
+/// Keep a parameter of an extension the library does not know, if it
+/// was advertised.
+fn extra_param(
+    seen: &mut Vec<String>,
+    keyword: String,
+    value: Option<&str>,
+    extensions: &EhloKeywords,
+) -> Result<(String, Option<String>), Reply> {
+    if !extensions.contains_key(&keyword) {
+        return Err(unsupported(&keyword));
+    }
+    check_param(seen, &keyword, &keyword, extensions)?;
+
+    Ok((keyword, value.map(Into::into)))
+}
+
+fn unsupported(keyword: &str) -> Reply {
+    Reply::new(
+        555,
+        Some(EnhancedCode(5, 5, 4)),
+        format!("Unsupported parameter {}", keyword),
+    )
+}
+
+fn invalid_value(keyword: &str) -> Reply {
+    Reply::new(
+        501,
+        Some(EnhancedCode(5, 5, 4)),
+        format!("Invalid {} parameter", keyword),
+    )
+}
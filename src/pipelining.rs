@@ -0,0 +1,27 @@
+use std::time::Duration;
+
+/// Action taken on command pipelining that breaks RFC 2920.
+///
+/// Violations are always reported to the handler, see
+/// [`Handler::pipelining_violation`](crate::Handler::pipelining_violation).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PipeliningPolicy {
+    /// Reply 554 5.5.0 and close the connection.
+    Reject,
+    /// Delay the offending command.
+    Tarpit(Duration),
+    /// Only report to the handler.
+    Flag,
+}
+
+/// Improper command pipelining, detected when more input was already
+/// waiting after a command.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PipeliningViolation {
+    /// Input sent after a command that must end a pipelined group,
+    /// such as EHLO, DATA, QUIT, NOOP, AUTH or BDAT LAST.
+    AfterGroupEnd(&'static str),
+    /// Commands pipelined before EHLO or without PIPELINING having
+    /// been advertised.
+    NotAdvertised,
+}
@@ -0,0 +1,436 @@
+use std::convert::TryInto;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use bytes::BytesMut;
+use tokio::prelude::*;
+
+use crate::ServerError;
+
+const V1_SIGNATURE: &[u8] = b"PROXY ";
+/// Length of `PROXY UNKNOWN\r\n`, the shortest header.
+const V1_MIN_LENGTH: usize = 15;
+const V1_MAX_LENGTH: usize = 107;
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+const V2_HEADER_LENGTH: usize = 16;
+
+pub const PP2_TYPE_ALPN: u8 = 0x01;
+pub const PP2_TYPE_AUTHORITY: u8 = 0x02;
+pub const PP2_TYPE_CRC32C: u8 = 0x03;
+pub const PP2_TYPE_NOOP: u8 = 0x04;
+pub const PP2_TYPE_UNIQUE_ID: u8 = 0x05;
+pub const PP2_TYPE_SSL: u8 = 0x20;
+pub const PP2_TYPE_NETNS: u8 = 0x30;
+
+/// HAProxy PROXY protocol handling before the banner.
+///
+/// Headers are only read from peers approved by
+/// [`Handler::proxy_allowed`](crate::Handler::proxy_allowed). The
+/// whole header must arrive within the given time.
+///
+/// See <https://www.haproxy.org/download/2.0/doc/proxy-protocol.txt>.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProxyProtocol {
+    Disabled,
+    /// Connections without a valid v1 or v2 header are dropped.
+    Required(Duration),
+    /// Wait up to the given time for a header before sending the
+    /// banner. Plain SMTP clients wait for the banner, so they only
+    /// see it delayed.
+    Optional(Duration),
+}
+
+/// Connection information received in a PROXY header.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProxyHeader {
+    /// Protocol version, 1 or 2.
+    pub version: u8,
+    /// Original client address. `None` for v1 UNKNOWN, v2 LOCAL and
+    /// non-IP v2 connections.
+    pub source: Option<SocketAddr>,
+    /// Address the client connected to, `None` like `source`.
+    pub destination: Option<SocketAddr>,
+    /// v2 type-length-value fields in order, always empty for v1.
+    /// The CRC32C checksum is not verified.
+    pub tlvs: Vec<ProxyTlv>,
+}
+
+/// PROXY protocol v2 TLV field, `kind` is one of the `PP2_TYPE_*`
+/// constants or an application specific value.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProxyTlv {
+    pub kind: u8,
+    pub value: Vec<u8>,
+}
+
+impl ProxyHeader {
+    /// Value of the first TLV of type `kind`.
+    pub fn tlv(&self, kind: u8) -> Option<&[u8]> {
+        self.tlvs
+            .iter()
+            .find(|tlv| tlv.kind == kind)
+            .map(|tlv| tlv.value.as_slice())
+    }
+}
+
+enum Parsed {
+    Complete(ProxyHeader),
+    /// At least this many more bytes are needed.
+    Incomplete(usize),
+    /// The input does not start with a PROXY signature.
+    NotProxy,
+    Invalid,
+}
+
+fn parse(input: &[u8]) -> Parsed {
+    if input.is_empty() {
+        // Reading more could consume SMTP input past a short v1
+        // header.
+        Parsed::Incomplete(V1_MIN_LENGTH)
+    } else if input.len() < V2_HEADER_LENGTH
+        && (V2_SIGNATURE.starts_with(input) || input.starts_with(V2_SIGNATURE))
+    {
+        Parsed::Incomplete(V2_HEADER_LENGTH - input.len())
+    } else if input.starts_with(V2_SIGNATURE) {
+        parse_v2(input)
+    } else if V1_SIGNATURE.starts_with(input) {
+        Parsed::Incomplete(1)
+    } else if input.starts_with(V1_SIGNATURE) {
+        parse_v1(input)
+    } else {
+        Parsed::NotProxy
+    }
+}
+
+fn parse_v1(input: &[u8]) -> Parsed {
+    let line = match input.strip_suffix(b"\r\n") {
+        Some(line) => line,
+        None if input.len() < V1_MAX_LENGTH => return Parsed::Incomplete(1),
+        None => return Parsed::Invalid,
+    };
+    let line = match std::str::from_utf8(&line[V1_SIGNATURE.len()..]) {
+        Ok(line) => line,
+        Err(_) => return Parsed::Invalid,
+    };
+
+    let mut fields = line.split(' ');
+    let (source, destination) = match fields.next() {
+        Some("UNKNOWN") => (None, None),
+        Some(family @ "TCP4") | Some(family @ "TCP6") => {
+            let addresses = (|| {
+                let source: IpAddr = fields.next()?.parse().ok()?;
+                let destination: IpAddr = fields.next()?.parse().ok()?;
+                let source_port = fields.next()?.parse().ok()?;
+                let destination_port = fields.next()?.parse().ok()?;
+                if fields.next().is_some()
+                    || source.is_ipv4() != (family == "TCP4")
+                    || destination.is_ipv4() != (family == "TCP4")
+                {
+                    return None;
+                }
+                Some((
+                    SocketAddr::new(source, source_port),
+                    SocketAddr::new(destination, destination_port),
+                ))
+            })();
+            match addresses {
+                Some((source, destination)) => (Some(source), Some(destination)),
+                None => return Parsed::Invalid,
+            }
+        }
+        _ => return Parsed::Invalid,
+    };
+
+    Parsed::Complete(ProxyHeader {
+        version: 1,
+        source,
+        destination,
+        tlvs: Vec::new(),
+    })
+}
+
+fn parse_v2(input: &[u8]) -> Parsed {
+    let length = u16::from_be_bytes([input[14], input[15]]) as usize;
+    if input.len() < V2_HEADER_LENGTH + length {
+        return Parsed::Incomplete(V2_HEADER_LENGTH + length - input.len());
+    }
+    let payload = &input[V2_HEADER_LENGTH..V2_HEADER_LENGTH + length];
+
+    let local = match input[12] {
+        0x20 => true,
+        0x21 => false,
+        _ => return Parsed::Invalid,
+    };
+    let address_length = match input[13] >> 4 {
+        0x0 => 0,
+        0x1 => 12,
+        0x2 => 36,
+        0x3 => 216,
+        _ => return Parsed::Invalid,
+    };
+    if payload.len() < address_length {
+        return Parsed::Invalid;
+    }
+
+    // LOCAL connections come from the proxy itself, their address
+    // block and TLVs are ignored.
+    if local {
+        return Parsed::Complete(ProxyHeader {
+            version: 2,
+            source: None,
+            destination: None,
+            tlvs: Vec::new(),
+        });
+    }
+
+    let (addresses, mut tlv_input) = payload.split_at(address_length);
+    let (source, destination) = match address_length {
+        12 => {
+            let ip = |offset: usize| {
+                let octets: [u8; 4] = addresses[offset..offset + 4].try_into().unwrap();
+                IpAddr::from(Ipv4Addr::from(octets))
+            };
+            let port =
+                |offset: usize| u16::from_be_bytes([addresses[offset], addresses[offset + 1]]);
+            (
+                Some(SocketAddr::new(ip(0), port(8))),
+                Some(SocketAddr::new(ip(4), port(10))),
+            )
+        }
+        36 => {
+            let ip = |offset: usize| {
+                let octets: [u8; 16] = addresses[offset..offset + 16].try_into().unwrap();
+                IpAddr::from(Ipv6Addr::from(octets))
+            };
+            let port =
+                |offset: usize| u16::from_be_bytes([addresses[offset], addresses[offset + 1]]);
+            (
+                Some(SocketAddr::new(ip(0), port(32))),
+                Some(SocketAddr::new(ip(16), port(34))),
+            )
+        }
+        _ => (None, None),
+    };
+
+    let mut tlvs = Vec::new();
+    while !tlv_input.is_empty() {
+        if tlv_input.len() < 3 {
+            return Parsed::Invalid;
+        }
+        let value_length = u16::from_be_bytes([tlv_input[1], tlv_input[2]]) as usize;
+        if tlv_input.len() < 3 + value_length {
+            return Parsed::Invalid;
+        }
+        tlvs.push(ProxyTlv {
+            kind: tlv_input[0],
+            value: tlv_input[3..3 + value_length].to_vec(),
+        });
+        tlv_input = &tlv_input[3 + value_length..];
+    }
+
+    Parsed::Complete(ProxyHeader {
+        version: 2,
+        source,
+        destination,
+        tlvs,
+    })
+}
+
+/// Read a PROXY header from the start of `socket`.
+///
+/// Only the bytes of the header are consumed. When no header is
+/// found with [`ProxyProtocol::Optional`], the bytes read so far are
+/// returned to be processed as SMTP.
+pub(crate) async fn read_proxy_header<S>(
+    socket: &mut S,
+    mode: ProxyProtocol,
+) -> Result<(Option<ProxyHeader>, BytesMut), ServerError>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut buf = BytesMut::new();
+    let (wait, optional) = match mode {
+        ProxyProtocol::Disabled => return Ok((None, buf)),
+        ProxyProtocol::Required(wait) => (wait, false),
+        ProxyProtocol::Optional(wait) => (wait, true),
+    };
+
+    let res = tokio::time::timeout(wait, read_header(socket, optional, &mut buf)).await;
+    match res {
+        Ok(res) => res,
+        // Nothing was sent, a client waiting for the banner.
+        Err(_) if optional && buf.is_empty() => Ok((None, buf)),
+        Err(_) => Err(ServerError::ProxyProtocol),
+    }
+}
+
+async fn read_header<S>(
+    socket: &mut S,
+    optional: bool,
+    buf: &mut BytesMut,
+) -> Result<(Option<ProxyHeader>, BytesMut), ServerError>
+where
+    S: AsyncRead + Unpin,
+{
+    loop {
+        let wanted = match parse(buf) {
+            Parsed::Complete(header) => return Ok((Some(header), BytesMut::new())),
+            Parsed::Incomplete(wanted) => wanted,
+            Parsed::NotProxy if optional => return Ok((None, buf.split())),
+            Parsed::NotProxy | Parsed::Invalid => return Err(ServerError::ProxyProtocol),
+        };
+
+        // buf only grows once a read completes, the caller checks it
+        // after a timeout.
+        let mut chunk = vec![0; wanted];
+        let read = socket.read(&mut chunk).await?;
+        if read == 0 {
+            return Err(ServerError::EOF);
+        }
+        buf.extend_from_slice(&chunk[..read]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn complete(input: &[u8]) -> ProxyHeader {
+        match parse(input) {
+            Parsed::Complete(header) => header,
+            _ => panic!("expected a complete header"),
+        }
+    }
+
+    fn v2(command: u8, family: u8, payload: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[command, family]);
+        header.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        header.extend_from_slice(payload);
+        header
+    }
+
+    fn read(input: &[u8], mode: ProxyProtocol) -> (Result<Option<ProxyHeader>, ()>, Vec<u8>) {
+        let mut runtime = tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_time()
+            .build()
+            .unwrap();
+        let mut socket = input;
+
+        let res = runtime.block_on(read_proxy_header(&mut socket, mode));
+        match res {
+            Ok((header, mut read_buf)) => {
+                read_buf.extend_from_slice(socket);
+                (Ok(header), read_buf.to_vec())
+            }
+            Err(_) => (Err(()), socket.to_vec()),
+        }
+    }
+
+    #[test]
+    fn v1() {
+        let header = complete(b"PROXY TCP4 192.0.2.1 198.51.100.1 40000 25\r\n");
+        assert_eq!(header.version, 1);
+        assert_eq!(header.source, Some("192.0.2.1:40000".parse().unwrap()));
+        assert_eq!(header.destination, Some("198.51.100.1:25".parse().unwrap()));
+
+        let header = complete(b"PROXY TCP6 2001:db8::1 2001:db8::2 40000 25\r\n");
+        assert_eq!(header.source, Some("[2001:db8::1]:40000".parse().unwrap()));
+
+        let header = complete(b"PROXY UNKNOWN\r\n");
+        assert_eq!((header.source, header.destination), (None, None));
+        let header = complete(b"PROXY UNKNOWN ignored fields\r\n");
+        assert_eq!(header.source, None);
+    }
+
+    #[test]
+    fn v1_invalid() {
+        for input in [
+            &b"PROXY TCP4 2001:db8::1 192.0.2.1 40000 25\r\n"[..],
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 40000\r\n",
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 40000 25 extra\r\n",
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 40000 http\r\n",
+            b"PROXY UDP4 192.0.2.1 198.51.100.1 40000 25\r\n",
+        ] {
+            assert!(matches!(parse(input), Parsed::Invalid));
+        }
+        let long = [&b"PROXY UNKNOWN "[..], &[b'x'; 100]].concat();
+        assert!(matches!(parse(&long), Parsed::Invalid));
+    }
+
+    #[test]
+    fn v2_addresses_and_tlvs() {
+        let mut payload = vec![192, 0, 2, 1, 198, 51, 100, 1];
+        payload.extend_from_slice(&40000u16.to_be_bytes());
+        payload.extend_from_slice(&25u16.to_be_bytes());
+        payload.extend_from_slice(&[PP2_TYPE_AUTHORITY, 0, 3, b'm', b'x', b'1']);
+        let header = complete(&v2(0x21, 0x11, &payload));
+
+        assert_eq!(header.version, 2);
+        assert_eq!(header.source, Some("192.0.2.1:40000".parse().unwrap()));
+        assert_eq!(header.destination, Some("198.51.100.1:25".parse().unwrap()));
+        assert_eq!(header.tlv(PP2_TYPE_AUTHORITY), Some(&b"mx1"[..]));
+        assert_eq!(header.tlv(PP2_TYPE_ALPN), None);
+
+        let mut payload = [0; 36].to_vec();
+        payload[15] = 1;
+        payload[31] = 2;
+        let header = complete(&v2(0x21, 0x21, &payload));
+        assert_eq!(header.source, Some("[::1]:0".parse().unwrap()));
+        assert_eq!(header.destination, Some("[::2]:0".parse().unwrap()));
+    }
+
+    #[test]
+    fn v2_local_and_invalid() {
+        let header = complete(&v2(0x20, 0x11, &[0; 12]));
+        assert_eq!(header.source, None);
+
+        // Bad version and command, short address block, truncated TLV.
+        assert!(matches!(parse(&v2(0x11, 0x11, &[0; 12])), Parsed::Invalid));
+        assert!(matches!(parse(&v2(0x22, 0x11, &[0; 12])), Parsed::Invalid));
+        assert!(matches!(parse(&v2(0x21, 0x11, &[0; 8])), Parsed::Invalid));
+        let tlv = [&[0; 12][..], &[PP2_TYPE_NOOP, 0, 5, 0]].concat();
+        assert!(matches!(parse(&v2(0x21, 0x11, &tlv)), Parsed::Invalid));
+    }
+
+    #[test]
+    fn truncated() {
+        let header = v2(0x21, 0x11, &[0; 12]);
+        for length in 0..header.len() {
+            assert!(matches!(parse(&header[..length]), Parsed::Incomplete(_)));
+        }
+        let header = b"PROXY TCP4 192.0.2.1 198.51.100.1 40000 25\r\n";
+        for length in 0..header.len() {
+            assert!(matches!(parse(&header[..length]), Parsed::Incomplete(_)));
+        }
+        assert!(matches!(parse(b"EHLO"), Parsed::NotProxy));
+    }
+
+    /// Reads never go past the header into the SMTP input.
+    #[test]
+    fn read_exact_header() {
+        let wait = Duration::from_secs(1);
+
+        let (header, rest) = read(
+            b"PROXY UNKNOWN\r\nEHLO x\r\n",
+            ProxyProtocol::Required(wait),
+        );
+        assert_eq!(header.unwrap().unwrap().source, None);
+        assert_eq!(rest, b"EHLO x\r\n");
+
+        let input = [&v2(0x20, 0x00, &[])[..], b"EHLO x\r\n"].concat();
+        let (header, rest) = read(&input, ProxyProtocol::Required(wait));
+        assert_eq!(header.unwrap().unwrap().version, 2);
+        assert_eq!(rest, b"EHLO x\r\n");
+
+        let (header, rest) = read(b"EHLO x\r\n", ProxyProtocol::Optional(wait));
+        assert_eq!(header, Ok(None));
+        assert_eq!(rest, b"EHLO x\r\n");
+
+        let (header, _) = read(b"EHLO x\r\n", ProxyProtocol::Required(wait));
+        assert_eq!(header, Err(()));
+        let (header, _) = read(b"PROXY TCP4", ProxyProtocol::Optional(wait));
+        assert_eq!(header, Err(()));
+    }
+}
@@ -1,10 +1,13 @@
 use std::borrow::Cow;
 use std::fmt::Display;
+use std::time::Duration;
 
+#[derive(Clone)]
 pub struct Reply {
     code: u16,
     ecode: Option<EnhancedCode>,
     text: Cow<'static, str>,
+    delay: Option<Duration>,
 }
 
 impl Reply {
@@ -14,10 +17,15 @@ impl Reply {
         text: S,
     ) -> Option<Self> {
         let text = text.into();
-        if code < 200 || code >= 600 || text.contains('\r') {
+        if !(200..600).contains(&code) || text.contains('\r') {
             return None;
         }
-        Some(Reply { code, ecode, text })
+        Some(Reply {
+            code,
+            ecode,
+            text,
+            delay: None,
+        })
     }
 
     pub fn new<S: Into<Cow<'static, str>>>(
@@ -28,6 +36,17 @@ impl Reply {
         Self::new_checked(code, ecode, text).expect("Invalid code or CR in reply text.")
     }
 
+    /// Send the reply after `delay`, in place of the delay from
+    /// [`Config::tarpit`](crate::Config::tarpit).
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+
+    pub fn delay(&self) -> Option<Duration> {
+        self.delay
+    }
+
     pub fn ok() -> Self {
         Self::new(250, None, "OK")
     }
@@ -48,10 +67,42 @@ impl Reply {
         Self::new(500, None, "Syntax error")
     }
 
+    pub fn line_too_long() -> Self {
+        Self::new(500, Some(EnhancedCode(5, 5, 2)), "Line too long")
+    }
+
     pub fn not_implemented() -> Self {
         Self::new(502, None, "Command not implemented")
     }
 
+    pub fn improper_pipelining() -> Self {
+        Self::new(
+            554,
+            Some(EnhancedCode(5, 5, 0)),
+            "Improper use of SMTP command pipelining",
+        )
+    }
+
+    pub fn timeout() -> Self {
+        Self::new(
+            421,
+            Some(EnhancedCode(4, 4, 2)),
+            "Timeout, closing connection",
+        )
+    }
+
+    pub fn too_many_errors() -> Self {
+        Self::new(421, Some(EnhancedCode(4, 7, 0)), "Too many errors")
+    }
+
+    pub fn too_many_recipients() -> Self {
+        Self::new(452, Some(EnhancedCode(4, 5, 3)), "Too many recipients")
+    }
+
+    pub fn too_many_transactions() -> Self {
+        Self::new(421, Some(EnhancedCode(4, 7, 0)), "Too many transactions")
+    }
+
     pub fn data_ok() -> Self {
         Self::new(354, None, "OK, send data")
     }
@@ -62,19 +113,75 @@ impl Reply {
         Self::new(450, None, "Data abort")
     }
 
+    pub fn message_too_large() -> Self {
+        Self::new(
+            552,
+            Some(EnhancedCode(5, 3, 4)),
+            "Message size exceeds fixed maximum message size",
+        )
+    }
+
+    pub fn message_line_too_long() -> Self {
+        Self::new(
+            554,
+            Some(EnhancedCode(5, 6, 0)),
+            "Message contains a line too long",
+        )
+    }
+
+    pub fn bare_newline() -> Self {
+        Self::new(
+            550,
+            Some(EnhancedCode(5, 6, 0)),
+            "Message contains bare CR or LF",
+        )
+    }
+
+    pub fn auth_ok() -> Self {
+        Self::new(
+            235,
+            Some(EnhancedCode(2, 7, 0)),
+            "Authentication successful",
+        )
+    }
+
+    pub fn auth_failed() -> Self {
+        Self::new(
+            535,
+            Some(EnhancedCode(5, 7, 8)),
+            "Authentication credentials invalid",
+        )
+    }
+
+    pub fn auth_malformed() -> Self {
+        Self::new(
+            501,
+            Some(EnhancedCode(5, 5, 2)),
+            "Malformed authentication data",
+        )
+    }
+
+    pub fn is_success(&self) -> bool {
+        ReplyCategory::from(self) == ReplyCategory::Success
+    }
+
     pub fn is_error(&self) -> bool {
-        match ReplyCategory::from(self) {
-            ReplyCategory::TempError | ReplyCategory::PermError => true,
-            _ => false,
-        }
+        matches!(
+            ReplyCategory::from(self),
+            ReplyCategory::TempError | ReplyCategory::PermError
+        )
     }
 }
 
 pub(crate) trait ReplyDefault {
-    fn with_default(self, default: Reply) -> Result<Reply, Reply>;
+    type Output;
+
+    fn with_default(self, default: Reply) -> Result<Self::Output, Self::Output>;
 }
 
 impl ReplyDefault for Option<Reply> {
+    type Output = Reply;
+
     fn with_default(self, default: Reply) -> Result<Reply, Reply> {
         let expected_category = ReplyCategory::from(&default);
         let reply = self.unwrap_or(default);
@@ -112,6 +219,16 @@ impl From<&Reply> for ReplyCategory {
 
 impl Display for Reply {
     fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        // An empty text still needs a reply line, such as an empty
+        // AUTH challenge.
+        if self.text.is_empty() {
+            write!(fmt, "{} ", self.code)?;
+            if let Some(ecode) = &self.ecode {
+                write!(fmt, "{}", ecode)?;
+            }
+            return writeln!(fmt, "\r");
+        }
+
         let mut lines_iter = self.text.lines().peekable();
 
         loop {
@@ -138,6 +255,7 @@ impl Display for Reply {
     }
 }
 
+#[derive(Clone)]
 pub struct EnhancedCode(pub u8, pub u16, pub u16);
 
 impl Display for EnhancedCode {
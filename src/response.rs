@@ -0,0 +1,61 @@
+use crate::reply::ReplyDefault;
+use crate::Reply;
+
+/// What the server does after sending a handler's reply.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    /// Carry on with the session.
+    Continue,
+    /// Close the connection, such as after a 421 reply.
+    Disconnect,
+    /// Abort the mail transaction, as if RSET was received.
+    ResetTransaction,
+}
+
+/// A handler's reply to a command, with the action to take once it is
+/// sent.
+///
+/// Plain replies convert into a response that continues the session.
+#[derive(Clone)]
+pub struct Response {
+    pub reply: Reply,
+    pub action: Action,
+}
+
+impl Response {
+    pub fn new(reply: Reply, action: Action) -> Self {
+        Self { reply, action }
+    }
+
+    /// Send `reply`, then close the connection.
+    pub fn disconnect(reply: Reply) -> Self {
+        Self::new(reply, Action::Disconnect)
+    }
+
+    /// Send `reply`, then abort the mail transaction.
+    pub fn reset_transaction(reply: Reply) -> Self {
+        Self::new(reply, Action::ResetTransaction)
+    }
+}
+
+impl From<Reply> for Response {
+    fn from(reply: Reply) -> Self {
+        Self::new(reply, Action::Continue)
+    }
+}
+
+impl ReplyDefault for Option<Response> {
+    type Output = Response;
+
+    fn with_default(self, default: Reply) -> Result<Response, Response> {
+        let (reply, action) = match self {
+            Some(response) => (Some(response.reply), response.action),
+            None => (None, Action::Continue),
+        };
+
+        match reply.with_default(default) {
+            Ok(reply) => Ok(Response::new(reply, action)),
+            Err(reply) => Err(Response::new(reply, action)),
+        }
+    }
+}
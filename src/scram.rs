@@ -0,0 +1,344 @@
+use std::marker::PhantomData;
+use std::sync::{Arc, OnceLock};
+
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use crate::auth::constant_time_eq;
+use crate::{Credentials, Reply, SaslMechanism, SaslStep};
+
+/// Hash function used by a SCRAM mechanism (RFC 5802).
+pub trait ScramHash: Send + 'static {
+    /// SASL mechanism name, such as `"SCRAM-SHA-256"`.
+    const MECHANISM: &'static str;
+
+    fn hash(data: &[u8]) -> Vec<u8>;
+    fn hmac(key: &[u8], data: &[u8]) -> Vec<u8>;
+}
+
+impl ScramHash for Sha1 {
+    const MECHANISM: &'static str = "SCRAM-SHA-1";
+
+    fn hash(data: &[u8]) -> Vec<u8> {
+        Sha1::digest(data).to_vec()
+    }
+
+    fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut mac = Hmac::<Sha1>::new_from_slice(key).unwrap();
+        mac.update(data);
+        mac.finalize().into_bytes().to_vec()
+    }
+}
+
+impl ScramHash for Sha256 {
+    const MECHANISM: &'static str = "SCRAM-SHA-256";
+
+    fn hash(data: &[u8]) -> Vec<u8> {
+        Sha256::digest(data).to_vec()
+    }
+
+    fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+        mac.update(data);
+        mac.finalize().into_bytes().to_vec()
+    }
+}
+
+/// Stored SCRAM credentials for one user and hash function.
+#[derive(Clone, Debug)]
+pub struct ScramCredentials {
+    pub salt: Vec<u8>,
+    pub iterations: u32,
+    pub stored_key: Vec<u8>,
+    pub server_key: Vec<u8>,
+}
+
+impl ScramCredentials {
+    /// Derive the stored credentials from a cleartext password.
+    ///
+    /// The password is used as is, without SASLprep normalization.
+    pub fn from_password<H: ScramHash>(password: &str, salt: &[u8], iterations: u32) -> Self {
+        let salted = hi::<H>(password.as_bytes(), salt, iterations);
+        let client_key = H::hmac(&salted, b"Client Key");
+
+        Self {
+            salt: salt.to_vec(),
+            iterations,
+            stored_key: H::hash(&client_key),
+            server_key: H::hmac(&salted, b"Server Key"),
+        }
+    }
+}
+
+/// Source of SCRAM credentials, provided by the handler.
+pub trait ScramCredentialStore: Send + Sync {
+    /// Look up the credentials of `username` for `mechanism`.
+    ///
+    /// `mechanism` is the SASL name such as `"SCRAM-SHA-256"`, since
+    /// the stored keys depend on the hash function.
+    fn lookup(&self, mechanism: &str, username: &str) -> Option<ScramCredentials>;
+
+    /// Secret from which the salts of unknown users are derived, so
+    /// that they cannot be told apart from existing users.
+    ///
+    /// Defaults to a random secret for the life of the process.
+    /// Servers sharing a store should return the same secret, or the
+    /// salt of an unknown user changes between them.
+    fn unknown_user_secret(&self) -> &[u8] {
+        static SECRET: OnceLock<[u8; 32]> = OnceLock::new();
+        SECRET.get_or_init(rand::random)
+    }
+}
+
+pub type ScramSha1 = Scram<Sha1>;
+pub type ScramSha256 = Scram<Sha256>;
+
+/// Server side of the SCRAM mechanisms from RFC 5802 and RFC 7677.
+///
+/// Channel binding is not supported, so the -PLUS variants cannot be
+/// offered.
+pub struct Scram<H> {
+    store: Arc<dyn ScramCredentialStore>,
+    server_nonce: String,
+    state: ScramState,
+    _hash: PhantomData<H>,
+}
+
+enum ScramState {
+    Initial,
+    ClientFirst,
+    ServerFirst(Exchange),
+    ServerFinal {
+        authzid: Option<String>,
+        username: String,
+    },
+    Done,
+}
+
+/// Everything needed to check the client-final-message.
+struct Exchange {
+    gs2_header: String,
+    authzid: Option<String>,
+    username: String,
+    nonce: String,
+    client_first_bare: String,
+    server_first: String,
+    credentials: Option<ScramCredentials>,
+}
+
+impl<H: ScramHash> Scram<H> {
+    pub fn new(store: Arc<dyn ScramCredentialStore>) -> Self {
+        Self::with_server_nonce(store, base64::encode(&rand::random::<[u8; 18]>()))
+    }
+
+    /// Use a fixed server nonce. Only useful to reproduce test vectors.
+    pub fn with_server_nonce<S: Into<String>>(
+        store: Arc<dyn ScramCredentialStore>,
+        server_nonce: S,
+    ) -> Self {
+        Self {
+            store,
+            server_nonce: server_nonce.into(),
+            state: ScramState::Initial,
+            _hash: PhantomData,
+        }
+    }
+
+    fn client_first(&mut self, message: &str) -> Result<Vec<u8>, Reply> {
+        // gs2-header: cbind-flag "," [authzid] ","
+        let mut parts = message.splitn(3, ',');
+        let (cbind, authzid, bare) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(cbind), Some(authzid), Some(bare)) => (cbind, authzid, bare),
+            _ => return Err(Reply::auth_malformed()),
+        };
+        match cbind {
+            "n" | "y" => (),
+            _ => return Err(Reply::auth_malformed()),
+        }
+        let authzid = match authzid {
+            "" => None,
+            a => Some(
+                a.strip_prefix("a=")
+                    .and_then(decode_saslname)
+                    .ok_or_else(Reply::auth_malformed)?,
+            ),
+        };
+
+        let mut attrs = bare.split(',');
+        // No mandatory extension is supported (RFC 5802 section 5.1).
+        if bare.starts_with("m=") {
+            return Err(Reply::auth_malformed());
+        }
+        let username = attrs
+            .next()
+            .and_then(|a| a.strip_prefix("n="))
+            .and_then(decode_saslname)
+            .ok_or_else(Reply::auth_malformed)?;
+        let client_nonce = attrs
+            .next()
+            .and_then(|a| a.strip_prefix("r="))
+            .filter(|n| !n.is_empty() && n.bytes().all(|c| c.is_ascii_graphic() && c != b','))
+            .ok_or_else(Reply::auth_malformed)?;
+
+        let credentials = self.store.lookup(H::MECHANISM, &username);
+        // Unknown users get a plausible salt so they cannot be told
+        // apart before the proof is checked. It must not be computable
+        // by the client.
+        let (salt, iterations) = match &credentials {
+            Some(c) => (c.salt.clone(), c.iterations),
+            None => {
+                let mut salt = H::hmac(self.store.unknown_user_secret(), username.as_bytes());
+                salt.truncate(16);
+                (salt, 4096)
+            }
+        };
+
+        let nonce = format!("{}{}", client_nonce, self.server_nonce);
+        let server_first = format!("r={},s={},i={}", nonce, base64::encode(&salt), iterations);
+
+        self.state = ScramState::ServerFirst(Exchange {
+            gs2_header: message[..message.len() - bare.len()].into(),
+            authzid,
+            username,
+            nonce,
+            client_first_bare: bare.into(),
+            server_first: server_first.clone(),
+            credentials,
+        });
+
+        Ok(server_first.into_bytes())
+    }
+
+    fn client_final(&mut self, message: &str) -> Result<Vec<u8>, Reply> {
+        let exchange = match std::mem::replace(&mut self.state, ScramState::Done) {
+            ScramState::ServerFirst(exchange) => exchange,
+            _ => return Err(Reply::auth_malformed()),
+        };
+
+        let proof_offset = message.rfind(",p=").ok_or_else(Reply::auth_malformed)?;
+        let without_proof = &message[..proof_offset];
+        let proof =
+            base64::decode(&message[proof_offset + 3..]).map_err(|_| Reply::auth_malformed())?;
+
+        let mut attrs = without_proof.split(',');
+        let binding = attrs
+            .next()
+            .and_then(|a| a.strip_prefix("c="))
+            .and_then(|c| base64::decode(c).ok())
+            .ok_or_else(Reply::auth_malformed)?;
+        let final_nonce = attrs
+            .next()
+            .and_then(|a| a.strip_prefix("r="))
+            .ok_or_else(Reply::auth_malformed)?;
+
+        if binding != exchange.gs2_header.as_bytes() || final_nonce != exchange.nonce {
+            return Err(Reply::auth_malformed());
+        }
+
+        let credentials = exchange.credentials.ok_or_else(Reply::auth_failed)?;
+        let auth_message = format!(
+            "{},{},{}",
+            exchange.client_first_bare, exchange.server_first, without_proof
+        );
+
+        let client_signature = H::hmac(&credentials.stored_key, auth_message.as_bytes());
+        if proof.len() != client_signature.len() {
+            return Err(Reply::auth_failed());
+        }
+        let client_key: Vec<u8> = proof
+            .iter()
+            .zip(&client_signature)
+            .map(|(p, s)| p ^ s)
+            .collect();
+        if !constant_time_eq(&H::hash(&client_key), &credentials.stored_key) {
+            return Err(Reply::auth_failed());
+        }
+
+        let server_signature = H::hmac(&credentials.server_key, auth_message.as_bytes());
+        self.state = ScramState::ServerFinal {
+            authzid: exchange.authzid,
+            username: exchange.username,
+        };
+
+        Ok(format!("v={}", base64::encode(&server_signature)).into_bytes())
+    }
+}
+
+impl<H: ScramHash> SaslMechanism for Scram<H> {
+    fn step(&mut self, response: Option<&[u8]>) -> SaslStep {
+        let response = match response.map(std::str::from_utf8) {
+            Some(Ok(response)) => response,
+            Some(Err(_)) => return SaslStep::Failed(Reply::auth_malformed()),
+            None => {
+                return match self.state {
+                    ScramState::Initial => {
+                        self.state = ScramState::ClientFirst;
+                        SaslStep::Challenge(Vec::new())
+                    }
+                    _ => SaslStep::Failed(Reply::auth_malformed()),
+                }
+            }
+        };
+
+        let res = match self.state {
+            ScramState::Initial | ScramState::ClientFirst => self.client_first(response),
+            ScramState::ServerFirst(_) => self.client_final(response),
+            ScramState::ServerFinal { .. } if response.is_empty() => {
+                match std::mem::replace(&mut self.state, ScramState::Done) {
+                    ScramState::ServerFinal { authzid, username } => {
+                        return SaslStep::Done(Credentials::Verified {
+                            authzid,
+                            authcid: username,
+                        })
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            _ => Err(Reply::auth_malformed()),
+        };
+
+        match res {
+            Ok(challenge) => SaslStep::Challenge(challenge),
+            Err(reply) => SaslStep::Failed(reply),
+        }
+    }
+}
+
+fn decode_saslname(input: &str) -> Option<String> {
+    let mut out = String::with_capacity(input.len());
+    let mut chars = input.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '=' => match (chars.next(), chars.next()) {
+                (Some('2'), Some('C')) => out.push(','),
+                (Some('3'), Some('D')) => out.push('='),
+                _ => return None,
+            },
+            ',' => return None,
+            c => out.push(c),
+        }
+    }
+
+    if out.is_empty() {
+        None
+    } else {
+        Some(out)
+    }
+}
+
+/// PBKDF2 with the mechanism's HMAC, called Hi() in RFC 5802.
+fn hi<H: ScramHash>(password: &[u8], salt: &[u8], iterations: u32) -> Vec<u8> {
+    let mut input = salt.to_vec();
+    input.extend_from_slice(&1u32.to_be_bytes());
+
+    let mut u = H::hmac(password, &input);
+    let mut out = u.clone();
+    for _ in 1..iterations {
+        u = H::hmac(password, &u);
+        out.iter_mut().zip(&u).for_each(|(o, u)| *o ^= u);
+    }
+
+    out
+}
@@ -1,9 +1,11 @@
 use std::collections::BTreeMap;
 use std::fmt::Write;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::{
     atomic::{AtomicBool, Ordering},
-    Arc,
+    Arc, Mutex,
 };
+use std::time::Duration;
 
 use async_trait::async_trait;
 use bytes::{Buf, BytesMut};
@@ -12,19 +14,37 @@ use futures::future::ready;
 use futures::Sink;
 use futures_util::future::{select, Either, FusedFuture};
 use futures_util::sink::SinkExt;
-use futures_util::stream::{Stream, StreamExt};
+use futures_util::stream::{self, Stream, StreamExt, TryStreamExt};
 
 use tokio::prelude::*;
 use tokio_util::codec::{Framed, FramedParts};
 
+use crate::limits::{known_verb, ErrorCount};
+use crate::newline::{find_bare_newlines, normalize_newlines, MAX_BARE_NEWLINE_REPORTS};
+use crate::proxy::read_proxy_header;
 use crate::reply::ReplyDefault;
+use crate::timeout::{Deadline, Timed};
+use crate::{builtin_mechanism, Credentials, SaslMechanism, SaslStep, ScramCredentialStore};
 use crate::{command, Command, Command::Base, Command::*};
-use crate::{LineCodec, LineError, Reply};
+use crate::{
+    Action, DsnRequest, EnhancedCode, Envelope, EnvelopeRecipient, LineCodec, LineError,
+    MailParams, RcptParams, Reply, Response,
+};
+use crate::{BareNewline, BareNewlinePolicy, PipeliningPolicy, PipeliningViolation};
+use crate::{
+    Limits, ProxyHeader, ProxyProtocol, Rewind, Session, Tarpit, Timeouts, TlsAcceptor,
+    TransactionOutcome,
+};
+use crate::{Xclient, XclientParam, Xforward, XCLIENT_ATTRIBUTES, XFORWARD_ATTRIBUTES};
 
 use rustyknife::behaviour::{Intl, Legacy};
 use rustyknife::rfc5321::Command::*;
-use rustyknife::rfc5321::{ForwardPath, Param, ReversePath};
+use rustyknife::rfc5321::{bdat_command, ForwardPath, Param, ReversePath};
 use rustyknife::types::{Domain, DomainPart};
+use rustyknife::xforward::Param as XforwardParam;
+
+/// Time allowed to send the 421 reply once a timeout expired.
+const TIMEOUT_REPLY_LIMIT: Duration = Duration::from_secs(10);
 
 pub type EhloKeywords = BTreeMap<String, Option<String>>;
 pub type ShutdownSignal = dyn FusedFuture<Output = Result<(), ()>> + Send + Unpin;
@@ -36,90 +56,589 @@ where
 {
     type TlsConfig;
     type TlsSession;
+    /// Handler data attached to each [`Session`].
+    type SessionData: Send + Sync;
 
-    async fn tls_request(&mut self) -> Option<Self::TlsConfig> {
+    async fn tls_request(
+        &mut self,
+        _session: &Session<Self::SessionData>,
+    ) -> Option<Self::TlsConfig> {
         None
     }
 
-    async fn tls_started(&mut self, _session: &Self::TlsSession) {}
+    async fn tls_started(
+        &mut self,
+        _session: &Session<Self::SessionData>,
+        _tls_session: &Self::TlsSession,
+    ) {
+    }
+
+    /// Whether a PROXY protocol header may be read from the peer.
+    /// Only trusted load balancers should be allowed, identified by
+    /// [`Session::socket_peer_addr`].
+    ///
+    /// Refused peers are dropped with [`ProxyProtocol::Required`],
+    /// and served as plain SMTP clients with
+    /// [`ProxyProtocol::Optional`].
+    async fn proxy_allowed(&mut self, _session: &Session<Self::SessionData>) -> bool {
+        false
+    }
+
+    /// Called with the PROXY protocol header before the banner.
+    async fn proxy(&mut self, _session: &Session<Self::SessionData>, _header: &ProxyHeader) {}
+
+    /// Called when the client sent `data` before the banner, with
+    /// [`Config::greeting_delay`] set. A returned reply, such as a
+    /// 554, is sent instead of the banner and ends the session.
+    async fn early_talker(
+        &mut self,
+        _session: &Session<Self::SessionData>,
+        _data: &[u8],
+    ) -> Option<Reply> {
+        None
+    }
 
     async fn ehlo(
         &mut self,
+        session: &Session<Self::SessionData>,
         domain: DomainPart,
         initial_keywords: EhloKeywords,
-    ) -> Result<(String, EhloKeywords), Reply>;
-    async fn helo(&mut self, domain: Domain) -> Option<Reply>;
-    async fn rset(&mut self);
+    ) -> Result<(String, EhloKeywords), Response>;
+    async fn helo(
+        &mut self,
+        session: &Session<Self::SessionData>,
+        domain: Domain,
+    ) -> Option<Response>;
+    async fn rset(&mut self, session: &Session<Self::SessionData>);
 
-    async fn mail(&mut self, path: ReversePath, params: Vec<Param>) -> Option<Reply>;
-    async fn rcpt(&mut self, path: ForwardPath, params: Vec<Param>) -> Option<Reply>;
+    async fn mail(
+        &mut self,
+        session: &Session<Self::SessionData>,
+        path: ReversePath,
+        params: MailParams,
+    ) -> Option<Response>;
+    async fn rcpt(
+        &mut self,
+        session: &Session<Self::SessionData>,
+        path: ForwardPath,
+        params: RcptParams,
+    ) -> Option<Response>;
 
-    async fn data_start(&mut self) -> Option<Reply> {
+    /// Called on DATA. The action of a response accepting the
+    /// command is ignored.
+    async fn data_start(
+        &mut self,
+        _session: &Session<Self::SessionData>,
+        _envelope: &Envelope,
+    ) -> Option<Response> {
         None
     }
-    async fn data<S>(&mut self, stream: &mut S) -> Result<Option<Reply>, ServerError>
+    async fn data<S>(
+        &mut self,
+        session: &Session<Self::SessionData>,
+        stream: &mut S,
+        envelope: &Envelope,
+    ) -> Result<Option<Response>, ServerError>
     where
         S: Stream<Item = Result<BytesMut, LineError>> + Unpin + Send;
     async fn bdat<S>(
         &mut self,
+        session: &Session<Self::SessionData>,
         stream: &mut S,
         size: u64,
         last: bool,
-    ) -> Result<Option<Reply>, ServerError>
+        envelope: &Envelope,
+    ) -> Result<Option<Response>, ServerError>
     where
         S: Stream<Item = Result<BytesMut, LineError>> + Unpin + Send;
 
-    async fn unhandled_command(&mut self, _command: Command) -> Option<Reply> {
+    /// Receive a whole message as a single byte stream, whichever
+    /// transfer method the client used.
+    ///
+    /// Only called when [`Config::unified_message`] is set, in place
+    /// of the `data`, `bdat` and LMTP variants. DATA bodies are
+    /// dot-unstuffed. With BDAT, the library replies to intermediate
+    /// chunks and reads the next BDAT command as `stream` is polled.
+    /// Another command in its place ends `stream` with
+    /// [`LineError::DataAbort`], a chunk over `max_message_size` with
+    /// [`LineError::MessageTooLarge`]; the message is then failed
+    /// whatever the reply. Chunk boundaries in `stream` carry no
+    /// meaning. In LMTP mode the reply is sent for every recipient.
+    /// The default forwards to `data`.
+    async fn message<S>(
+        &mut self,
+        session: &Session<Self::SessionData>,
+        stream: &mut S,
+        envelope: &Envelope,
+    ) -> Result<Option<Response>, ServerError>
+    where
+        S: Stream<Item = Result<BytesMut, LineError>> + Unpin + Send,
+    {
+        self.data(session, stream, envelope).await
+    }
+
+    /// LMTP variant of [`Handler::data`] returning one reply per
+    /// recipient, in the same order as `envelope.recipients`.
+    ///
+    /// Missing responses are filled with [`Reply::data_abort`]. The
+    /// default sends the reply from `data` for every recipient.
+    async fn lmtp_data<S>(
+        &mut self,
+        session: &Session<Self::SessionData>,
+        stream: &mut S,
+        envelope: &Envelope,
+    ) -> Result<Vec<Response>, ServerError>
+    where
+        S: Stream<Item = Result<BytesMut, LineError>> + Unpin + Send,
+    {
+        let response = self
+            .data(session, stream, envelope)
+            .await?
+            .unwrap_or_else(|| Reply::ok().into());
+        Ok(envelope
+            .recipients
+            .iter()
+            .map(|_| response.clone())
+            .collect())
+    }
+
+    /// LMTP variant of [`Handler::bdat`] for the last chunk.
+    ///
+    /// Earlier chunks go through `bdat`. The default sends the reply
+    /// from `bdat` for every recipient.
+    async fn lmtp_bdat<S>(
+        &mut self,
+        session: &Session<Self::SessionData>,
+        stream: &mut S,
+        size: u64,
+        envelope: &Envelope,
+    ) -> Result<Vec<Response>, ServerError>
+    where
+        S: Stream<Item = Result<BytesMut, LineError>> + Unpin + Send,
+    {
+        let response = self
+            .bdat(session, stream, size, true, envelope)
+            .await?
+            .unwrap_or_else(|| Reply::ok().into());
+        Ok(envelope
+            .recipients
+            .iter()
+            .map(|_| response.clone())
+            .collect())
+    }
+
+    /// Called for each bare CR or LF found in a DATA body, once the
+    /// body was read. At most 100 are reported per message.
+    async fn bare_newline(&mut self, _session: &Session<Self::SessionData>, _bare: &BareNewline) {}
+
+    /// Called on each improper use of command pipelining, before
+    /// [`Config::pipelining`] is applied.
+    async fn pipelining_violation(
+        &mut self,
+        _session: &Session<Self::SessionData>,
+        _violation: &PipeliningViolation,
+    ) {
+    }
+
+    /// Called when a mail transaction ends, before the envelope is
+    /// discarded.
+    async fn transaction_end(
+        &mut self,
+        _session: &Session<Self::SessionData>,
+        _envelope: &Envelope,
+        _outcome: TransactionOutcome,
+    ) {
+    }
+
+    /// Instantiate the SASL mechanism for an AUTH command.
+    ///
+    /// Only called for mechanisms listed in
+    /// [`Config::auth_mechanisms`]. `name` is uppercase and
+    /// `hostname` is [`Config::hostname`].
+    fn sasl_mechanism(
+        &mut self,
+        _session: &Session<Self::SessionData>,
+        name: &str,
+        hostname: &str,
+    ) -> Option<Box<dyn SaslMechanism>> {
+        builtin_mechanism(name, hostname, self.scram_credential_store())
+    }
+
+    /// Credential store for the SCRAM mechanisms. They are unavailable
+    /// when `None`.
+    fn scram_credential_store(&self) -> Option<Arc<dyn ScramCredentialStore>> {
+        None
+    }
+
+    /// Check the credentials collected by a SASL exchange.
+    ///
+    /// Returns the authenticated identity on success.
+    async fn authenticate(
+        &mut self,
+        _session: &Session<Self::SessionData>,
+        _credentials: Credentials,
+    ) -> Result<String, Reply> {
+        Err(Reply::auth_failed())
+    }
+
+    /// Whether the client may use XFORWARD. Only trusted peers such
+    /// as a Postfix content filter should be allowed, see
+    /// [`Handler::xclient_allowed`].
+    async fn xforward_allowed(&mut self, _session: &Session<Self::SessionData>) -> bool {
+        false
+    }
+
+    /// Called after each XFORWARD command with the attributes
+    /// accumulated for the next transaction.
+    async fn xforward(
+        &mut self,
+        _session: &Session<Self::SessionData>,
+        _attributes: &Xforward,
+    ) -> Option<Response> {
+        None
+    }
+
+    /// Whether the client may use XCLIENT. Only trusted front-end
+    /// proxies should be allowed, identified by
+    /// [`Session::socket_peer_addr`] since the client can override
+    /// `peer_addr`.
+    async fn xclient_allowed(&mut self, _session: &Session<Self::SessionData>) -> bool {
+        false
+    }
+
+    /// Called with the overridden client attributes, before the
+    /// session restarts. Returning a response rejects the command.
+    async fn xclient(
+        &mut self,
+        _session: &Session<Self::SessionData>,
+        _attributes: &Xclient,
+    ) -> Option<Response> {
+        None
+    }
+
+    async fn unhandled_command(
+        &mut self,
+        _session: &Session<Self::SessionData>,
+        _command: Command,
+    ) -> Option<Response> {
         None
     }
 }
 
 pub struct Config {
+    /// Server name in the banner and in CRAM-MD5 challenges.
+    pub hostname: String,
     pub enable_smtputf8: bool,
     pub enable_chunking: bool,
     pub enable_starttls: bool,
+    /// SASL mechanisms advertised with AUTH. AUTH is disabled when
+    /// empty.
+    pub auth_mechanisms: Vec<String>,
+    /// Speak LMTP (RFC 2033) instead of SMTP.
+    pub lmtp: bool,
+    /// Maximum message size advertised with SIZE (RFC 1870) and
+    /// enforced while receiving the body.
+    pub max_message_size: Option<u64>,
+    /// Advertise DSN (RFC 3461) and validate its parameters.
+    pub enable_dsn: bool,
+    /// Expect a PROXY protocol header before the banner, from peers
+    /// approved by [`Handler::proxy_allowed`].
+    pub proxy_protocol: ProxyProtocol,
+    /// Accept MAIL without a prior HELO or EHLO, against RFC 5321.
+    pub lenient_greeting: bool,
+    /// Deliver DATA and BDAT bodies through [`Handler::message`].
+    pub unified_message: bool,
+    /// Handling of bare CR and LF in DATA bodies.
+    pub bare_newline: BareNewlinePolicy,
+    /// Line and chunk limits, see [`LineCodec::builder`].
+    pub line_codec: LineCodec,
+    /// Action on command pipelining that breaks RFC 2920.
+    pub pipelining: PipeliningPolicy,
+    /// Wait this long before the banner to catch clients that talk
+    /// first.
+    pub greeting_delay: Option<Duration>,
+    /// Limits on waiting for the client and on message processing.
+    pub timeouts: Timeouts,
+    /// Limits on errors, recipients and transactions.
+    pub limits: Limits,
+    /// Progressive delay of error replies.
+    pub tarpit: Option<Tarpit>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
+            hostname: "localhost".into(),
             enable_smtputf8: true,
             enable_chunking: true,
             enable_starttls: true,
+            auth_mechanisms: Vec::new(),
+            lmtp: false,
+            max_message_size: None,
+            enable_dsn: false,
+            proxy_protocol: ProxyProtocol::Disabled,
+            lenient_greeting: false,
+            unified_message: false,
+            bare_newline: BareNewlinePolicy::Normalize,
+            line_codec: LineCodec::default(),
+            pipelining: PipeliningPolicy::Flag,
+            greeting_delay: None,
+            timeouts: Timeouts::default(),
+            limits: Limits::default(),
+            tarpit: None,
         }
     }
 }
 
+/// Serve a session on `socket`.
+///
+/// `banner` is false when resuming after STARTTLS, the caller having
+/// done the handshake. The session is then marked as secured.
 pub async fn smtp_server<S, H>(
     socket: &mut S,
     handler: &mut H,
+    session: &mut Session<H::SessionData>,
+    config: &Config,
+    shutdown: &mut ShutdownSignal,
+    banner: bool,
+) -> Result<LoopExit<H>, ServerError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+    H: Handler,
+{
+    let read_buf = if banner {
+        proxy_stage(socket, handler, session, config).await?
+    } else {
+        session.tls_started();
+        BytesMut::new()
+    };
+
+    run_server(socket, handler, session, config, shutdown, banner, read_buf).await
+}
+
+/// Serve a session over implicit TLS (SMTPS, RFC 8314).
+///
+/// The handshake uses the configuration from
+/// [`Handler::tls_request`] and happens before the banner. The
+/// connection is closed if no configuration is returned. STARTTLS is
+/// never offered.
+pub async fn smtps_server<S, H, A>(
+    mut socket: S,
+    handler: &mut H,
+    session: &mut Session<H::SessionData>,
+    config: &Config,
+    shutdown: &mut ShutdownSignal,
+    acceptor: &A,
+) -> Result<LoopExit<H>, ServerError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    H: Handler<TlsConfig = A::Config, TlsSession = A::Session>,
+    A: TlsAcceptor<Rewind<S>>,
+{
+    let read_buf = proxy_stage(&mut socket, handler, session, config).await?;
+
+    let tls_config = match handler.tls_request(session).await {
+        Some(tls_config) => tls_config,
+        None => return Ok(LoopExit::Done),
+    };
+    let socket = Rewind::new(socket, read_buf);
+    let mut tls_socket = tls_handshake(config, session, acceptor, tls_config, socket).await?;
+    session.tls_started();
+    handler.tls_started(session, A::session(&tls_socket)).await;
+
+    let res = run_server(
+        &mut tls_socket,
+        handler,
+        session,
+        config,
+        shutdown,
+        true,
+        BytesMut::new(),
+    )
+    .await;
+    // The session result matters more than a failed close_notify.
+    let _ = tls_socket.shutdown().await;
+    res
+}
+
+/// Serve a whole session, including the STARTTLS upgrade.
+///
+/// After the handshake the session starts over without a banner and
+/// the client must send EHLO again (RFC 3207 section 4.2).
+pub async fn smtp_session<S, H, A>(
+    mut socket: S,
+    handler: &mut H,
+    session: &mut Session<H::SessionData>,
+    config: &Config,
+    shutdown: &mut ShutdownSignal,
+    acceptor: &A,
+) -> Result<(), ServerError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    H: Handler<TlsConfig = A::Config, TlsSession = A::Session>,
+    A: TlsAcceptor<Rewind<S>>,
+{
+    let read_buf = proxy_stage(&mut socket, handler, session, config).await?;
+    let mut socket = Rewind::new(socket, read_buf);
+
+    let tls_config = match run_server(
+        &mut socket,
+        handler,
+        session,
+        config,
+        shutdown,
+        true,
+        BytesMut::new(),
+    )
+    .await?
+    {
+        LoopExit::Done | LoopExit::Disconnect => return Ok(()),
+        LoopExit::STARTTLS(tls_config) => tls_config,
+    };
+
+    let mut tls_socket = tls_handshake(config, session, acceptor, tls_config, socket).await?;
+    session.tls_started();
+    handler.tls_started(session, A::session(&tls_socket)).await;
+
+    let res = run_server(
+        &mut tls_socket,
+        handler,
+        session,
+        config,
+        shutdown,
+        false,
+        BytesMut::new(),
+    )
+    .await;
+    let _ = tls_socket.shutdown().await;
+    res.map(|_| ())
+}
+
+/// Read the PROXY header if configured, returning the bytes read
+/// past it.
+async fn proxy_stage<S, H>(
+    socket: &mut S,
+    handler: &mut H,
+    session: &mut Session<H::SessionData>,
+    config: &Config,
+) -> Result<BytesMut, ServerError>
+where
+    S: AsyncRead + Unpin,
+    H: Handler,
+{
+    if config.proxy_protocol == ProxyProtocol::Disabled {
+        return Ok(BytesMut::new());
+    }
+    if !handler.proxy_allowed(session).await {
+        return match config.proxy_protocol {
+            ProxyProtocol::Required(_) => Err(ServerError::ProxyProtocol),
+            _ => Ok(BytesMut::new()),
+        };
+    }
+
+    let (header, read_buf) = session_deadline(config, session)
+        .bounded(read_proxy_header(socket, config.proxy_protocol))
+        .await??;
+    if let Some(header) = header {
+        if header.source.is_some() {
+            session.peer_addr = header.source;
+            session.local_addr = header.destination;
+        }
+        handler.proxy(session, &header).await;
+    }
+
+    Ok(read_buf)
+}
+
+/// Perform the TLS handshake within [`Timeouts::tls_handshake`] and
+/// the session limit.
+async fn tls_handshake<S, D, A>(
+    config: &Config,
+    session: &Session<D>,
+    acceptor: &A,
+    tls_config: A::Config,
+    socket: S,
+) -> Result<A::Stream, ServerError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    A: TlsAcceptor<S>,
+{
+    let deadline = session_deadline(config, session);
+    deadline.command(config.timeouts.tls_handshake);
+
+    Ok(deadline
+        .bounded(acceptor.accept(tls_config, socket))
+        .await??)
+}
+
+/// Deadline for the whole session, counted from when the connection
+/// was accepted.
+fn session_deadline<D>(config: &Config, session: &Session<D>) -> Deadline {
+    Deadline::new(
+        config
+            .timeouts
+            .session
+            .and_then(|limit| session.started.checked_add(limit)),
+    )
+}
+
+async fn run_server<S, H>(
+    socket: &mut S,
+    handler: &mut H,
+    session: &mut Session<H::SessionData>,
     config: &Config,
     shutdown: &mut ShutdownSignal,
     banner: bool,
+    read_buf: BytesMut,
 ) -> Result<LoopExit<H>, ServerError>
 where
     S: AsyncRead + AsyncWrite + Unpin + Send,
     H: Handler,
 {
     let terminated = shutdown.is_terminated();
+    let deadline = session_deadline(config, session);
     let mut server = InnerServer {
         handler,
+        session,
         config,
         state: State::Initial,
         shutdown,
         shutdown_on_idle: terminated,
+        message_size: 0,
+        extensions: EhloKeywords::new(),
+        envelope: Envelope::default(),
+        greeted: false,
+        interrupted: None,
+        deadline,
     };
 
-    let res = server.serve(socket, banner).await;
+    let res = server.serve(socket, banner, read_buf).await;
+    let expired = server.deadline.expired();
+    if expired {
+        // Best effort, the client may not be reading either.
+        let reply = Reply::timeout().to_string();
+        let send = async {
+            socket.write_all(reply.as_bytes()).await?;
+            socket.flush().await
+        };
+        let _ = tokio::time::timeout(TIMEOUT_REPLY_LIMIT, send).await;
+    }
+    server.end_transaction(TransactionOutcome::Aborted).await;
+    if expired {
+        return Err(ServerError::Timeout);
+    }
     socket.flush().await?;
-    Ok(res?)
+    res
 }
 
 pub enum LoopExit<H: Handler> {
     Done,
     STARTTLS(H::TlsConfig),
+    /// A handler response asked to close the connection.
+    Disconnect,
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, PartialEq)]
 enum State {
     Initial,
@@ -129,12 +648,24 @@ enum State {
     BDATFAIL,
 }
 
-struct InnerServer<'a, H> {
+struct InnerServer<'a, H: Handler> {
     handler: &'a mut H,
+    session: &'a mut Session<H::SessionData>,
     config: &'a Config,
     state: State,
     shutdown: &'a mut ShutdownSignal,
     shutdown_on_idle: bool,
+    /// Bytes received so far with BDAT in the current transaction.
+    message_size: u64,
+    /// Keywords from the last EHLO reply.
+    extensions: EhloKeywords,
+    envelope: Envelope,
+    /// HELO or EHLO was accepted since the session (re)started.
+    greeted: bool,
+    /// Line read in place of a BDAT between the chunks of a unified
+    /// message, served as the next command.
+    interrupted: Option<Result<BytesMut, LineError>>,
+    deadline: Deadline,
 }
 
 impl<'a, H> InnerServer<'a, H>
@@ -145,23 +676,55 @@ where
         &mut self,
         base_socket: &mut S,
         banner: bool,
+        mut read_buf: BytesMut,
     ) -> Result<LoopExit<H>, ServerError>
     where
         S: AsyncRead + AsyncWrite + Unpin + Send,
     {
-        let mut socket = Framed::new(base_socket, LineCodec::default());
+        let mut timed = Timed::new(base_socket, self.deadline.clone());
+        let rejection = if banner {
+            self.greeting_delay(&mut timed, &mut read_buf).await?
+        } else {
+            None
+        };
+
+        let mut parts = FramedParts::new(timed, self.config.line_codec.clone());
+        parts.read_buf = read_buf;
+        let mut socket = Framed::from_parts(parts);
 
+        if let Some(reply) = rejection {
+            socket.send(reply).await?;
+            return Ok(LoopExit::Done);
+        }
         if banner {
-            socket
-                .send(Reply::new(220, None, "localhost ESMTP smtpbis 0.1.0"))
-                .await?;
+            socket.send(self.banner()).await?;
         }
 
         loop {
+            // Replies are held back while pipelined commands are
+            // already waiting, so that a batch goes out in a single
+            // write (RFC 2920 3.1).
+            if socket.read_buffer().is_empty() {
+                socket.flush().await?;
+            }
+            socket.codec_mut().set_extensions(&self.extensions);
+
             let cmd = match self.read_command(&mut socket).await {
                 Ok(cmd) => cmd,
-                Err(ServerError::SyntaxError(_)) => {
-                    socket.send(Reply::syntax_error()).await?;
+                Err(ServerError::SyntaxError(line)) => {
+                    let count = if known_verb(&line) {
+                        ErrorCount::SyntaxErrors
+                    } else {
+                        ErrorCount::UnknownCommands
+                    };
+                    self.count_error(&mut socket, count).await?;
+                    self.feed_reply(&mut socket, Reply::syntax_error()).await?;
+                    continue;
+                }
+                Err(ServerError::Framing(LineError::LineTooLong)) => {
+                    self.count_error(&mut socket, ErrorCount::SyntaxErrors)
+                        .await?;
+                    self.feed_reply(&mut socket, Reply::line_too_long()).await?;
                     continue;
                 }
                 Err(ServerError::Shutdown) => {
@@ -171,10 +734,22 @@ where
                 Err(e) => return Err(e),
             };
 
+            let group_end = group_end(&cmd);
+            // The chunk follows BDAT, only input past it counts.
+            let bdat = matches!(cmd, Ext(crate::Ext::BDAT(..)));
+            if !bdat {
+                let pending = !socket.read_buffer().is_empty();
+                if let Some(violation) = self.pipelining_violation(group_end, pending) {
+                    self.improper_pipelining(&mut socket, violation).await?;
+                }
+            }
+
             match self.dispatch_command(&mut socket, cmd).await? {
                 Some(LoopExit::STARTTLS(tls_config)) => {
                     socket.flush().await?;
-                    let FramedParts { io, read_buf, .. } = socket.into_parts();
+                    let FramedParts {
+                        mut io, read_buf, ..
+                    } = socket.into_parts();
                     // Absolutely do not allow pipelining past a
                     // STARTTLS command.
                     if !read_buf.is_empty() {
@@ -185,12 +760,178 @@ where
                     io.write_all(tls_reply.as_bytes()).await?;
                     return Ok(LoopExit::STARTTLS(tls_config));
                 }
-                Some(LoopExit::Done) => {
-                    return Ok(LoopExit::Done);
-                }
+                Some(exit) => return Ok(exit),
                 None => {}
             }
+
+            if bdat {
+                let pending = !socket.read_buffer().is_empty();
+                if let Some(violation) = self.pipelining_violation(group_end, pending) {
+                    self.improper_pipelining(&mut socket, violation).await?;
+                }
+            }
+        }
+    }
+
+    /// Count an error. Past the soft limit the reply is delayed, at
+    /// the hard limit 421 is sent in its place and the session ends.
+    async fn count_error<S>(&mut self, socket: &mut S, count: ErrorCount) -> Result<(), ServerError>
+    where
+        S: Sink<Reply> + Unpin,
+        ServerError: From<<S as Sink<Reply>>::Error>,
+    {
+        let limits = &self.config.limits;
+        let (count, limit) = match count {
+            ErrorCount::SyntaxErrors => {
+                self.session.syntax_errors += 1;
+                (self.session.syntax_errors, &limits.syntax_errors)
+            }
+            ErrorCount::UnknownCommands => {
+                self.session.unknown_commands += 1;
+                (self.session.unknown_commands, &limits.unknown_commands)
+            }
+            ErrorCount::RejectedRecipients => {
+                self.session.rejected_recipients += 1;
+                (
+                    self.session.rejected_recipients,
+                    &limits.rejected_recipients,
+                )
+            }
+        };
+
+        if limit.hard_reached(count) {
+            self.send_reply(socket, Reply::too_many_errors()).await?;
+            return Err(ServerError::LimitExceeded);
+        }
+        if limit.soft_exceeded(count) {
+            tokio::time::delay_for(limits.soft_delay).await;
+        }
+        Ok(())
+    }
+
+    /// Delay `reply` as requested with [`Reply::with_delay`], or per
+    /// [`Config::tarpit`] for error replies.
+    async fn tarpit(&mut self, reply: &Reply) {
+        if reply.is_error() {
+            self.session.error_replies += 1;
+        }
+
+        let errors = self.session.error_replies;
+        let delay = match (reply.delay(), &self.config.tarpit) {
+            (Some(delay), _) => delay,
+            (None, Some(tarpit)) if reply.is_error() => tarpit.delay(errors),
+            (None, _) => return,
+        };
+        if delay > Duration::from_secs(0) {
+            tokio::time::delay_for(delay).await;
+        }
+    }
+
+    async fn send_reply<S>(&mut self, socket: &mut S, reply: Reply) -> Result<(), ServerError>
+    where
+        S: Sink<Reply> + Unpin,
+        ServerError: From<<S as Sink<Reply>>::Error>,
+    {
+        self.tarpit(&reply).await;
+        Ok(socket.send(reply).await?)
+    }
+
+    /// Like `send_reply`, leaving the reply buffered.
+    async fn feed_reply<S>(&mut self, socket: &mut S, reply: Reply) -> Result<(), ServerError>
+    where
+        S: Sink<Reply> + Unpin,
+        ServerError: From<<S as Sink<Reply>>::Error>,
+    {
+        self.tarpit(&reply).await;
+        Ok(socket.feed(reply).await?)
+    }
+
+    fn command_timeout(&self) -> Duration {
+        let timeouts = &self.config.timeouts;
+
+        match self.state {
+            State::Initial if !self.greeted => timeouts.greeting,
+            State::Initial => timeouts.mail,
+            State::MAIL | State::RCPT => timeouts.rcpt,
+            State::BDAT | State::BDATFAIL => timeouts.data_block,
+        }
+    }
+
+    /// Check the input already waiting after a command ending a
+    /// group as `group_end`.
+    fn pipelining_violation(
+        &self,
+        group_end: Option<&'static str>,
+        pending: bool,
+    ) -> Option<PipeliningViolation> {
+        if !pending {
+            return None;
+        }
+
+        match group_end {
+            Some(command) => Some(PipeliningViolation::AfterGroupEnd(command)),
+            None if !self.extensions.contains_key("PIPELINING") => {
+                Some(PipeliningViolation::NotAdvertised)
+            }
+            None => None,
+        }
+    }
+
+    async fn improper_pipelining<S>(
+        &mut self,
+        socket: &mut S,
+        violation: PipeliningViolation,
+    ) -> Result<(), ServerError>
+    where
+        S: Sink<Reply> + Unpin,
+        ServerError: From<<S as Sink<Reply>>::Error>,
+    {
+        self.session.improper_pipelining = true;
+        self.handler
+            .pipelining_violation(self.session, &violation)
+            .await;
+
+        match self.config.pipelining {
+            PipeliningPolicy::Reject => {
+                self.send_reply(socket, Reply::improper_pipelining()).await?;
+                Err(ServerError::Pipelining)
+            }
+            PipeliningPolicy::Tarpit(delay) => {
+                tokio::time::delay_for(delay).await;
+                Ok(())
+            }
+            PipeliningPolicy::Flag => Ok(()),
+        }
+    }
+
+    /// Wait for [`Config::greeting_delay`], reporting input received
+    /// meanwhile, or left over from the PROXY header stage.
+    async fn greeting_delay<S>(
+        &mut self,
+        socket: &mut S,
+        read_buf: &mut BytesMut,
+    ) -> Result<Option<Reply>, ServerError>
+    where
+        S: AsyncRead + Unpin,
+    {
+        let delay = match self.config.greeting_delay {
+            Some(delay) => delay,
+            None => return Ok(None),
+        };
+
+        if read_buf.is_empty() {
+            read_buf.reserve(1024);
+            match tokio::time::timeout(delay, socket.read_buf(read_buf)).await {
+                Err(_) => return Ok(None),
+                Ok(Ok(0)) => return Err(ServerError::EOF),
+                Ok(res) => {
+                    res?;
+                }
+            }
         }
+
+        self.session.early_talker = true;
+        Ok(self.handler.early_talker(self.session, read_buf).await)
     }
 
     fn shutdown_check(&self) -> Result<(), ServerError> {
@@ -207,8 +948,11 @@ where
         ServerError: From<<S as Sink<Reply>>::Error>,
     {
         self.shutdown_check()?;
+        self.deadline.command(self.command_timeout());
 
-        let line = if self.shutdown.is_terminated() {
+        let line = if let Some(line) = self.interrupted.take() {
+            Some(line)
+        } else if self.shutdown.is_terminated() {
             reader.next().await
         } else {
             match select(reader.next(), &mut self.shutdown).await {
@@ -237,103 +981,253 @@ where
 
     async fn dispatch_command<S>(
         &mut self,
-        socket: &mut Framed<&mut S, LineCodec>,
+        socket: &mut Framed<Timed<&mut S>, LineCodec>,
         command: Command,
     ) -> Result<Option<LoopExit<H>>, ServerError>
     where
         S: AsyncRead + AsyncWrite + Unpin + Send,
     {
         match command {
+            Base(EHLO(_)) | Base(HELO(_)) if self.config.lmtp => {
+                let reply = Reply::new(500, None, "Use LHLO in LMTP mode");
+                self.send_reply(socket, reply).await?;
+            }
+            Ext(crate::Ext::LHLO(domain)) if self.config.lmtp => {
+                let response = self.do_ehlo(domain).await?;
+                return self.respond(socket, response, true).await;
+            }
             Base(EHLO(domain)) => {
-                socket.send(self.do_ehlo(domain).await?).await?;
+                let response = self.do_ehlo(domain).await?;
+                return self.respond(socket, response, true).await;
             }
             Base(HELO(domain)) => {
-                socket.send(self.do_helo(domain).await?).await?;
+                let response = self.do_helo(domain).await?;
+                return self.respond(socket, response, true).await;
+            }
+            Base(MAIL(..)) if self.transactions_exceeded() => {
+                self.send_reply(socket, Reply::too_many_transactions()).await?;
+                return Err(ServerError::LimitExceeded);
             }
             Base(MAIL(path, params)) => {
-                socket.send(self.do_mail(path, params).await?).await?;
+                let response = self.do_mail(path, params).await?;
+                return self.respond(socket, response, false).await;
+            }
+            Base(RCPT(..)) if self.recipients_exceeded() => {
+                self.feed_reply(socket, Reply::too_many_recipients())
+                    .await?;
             }
             Base(RCPT(path, params)) => {
-                socket.send(self.do_rcpt(path, params).await?).await?;
+                let response = self.do_rcpt(path, params).await?;
+                if response.reply.is_error() {
+                    self.count_error(socket, ErrorCount::RejectedRecipients)
+                        .await?;
+                }
+                return self.respond(socket, response, false).await;
             }
             Base(DATA) => {
-                let reply = self.do_data(socket).await?;
-                socket.send(reply).await?;
+                let responses = self.do_data(socket).await?;
+                return self.respond_all(socket, responses, true).await;
             }
             Base(QUIT) => {
                 socket.send(Reply::new(221, None, "bye")).await?;
                 return Ok(Some(LoopExit::Done));
             }
             Base(RSET) => {
-                self.state = State::Initial;
-                self.handler.rset().await;
-                socket.send(Reply::ok()).await?;
+                self.end_transaction(TransactionOutcome::Aborted).await;
+                self.handler.rset(self.session).await;
+                self.feed_reply(socket, Reply::ok()).await?;
             }
-            Ext(crate::Ext::STARTTLS) if self.config.enable_starttls => {
-                if let Some(tls_config) = self.handler.tls_request().await {
+            Ext(crate::Ext::STARTTLS) if self.config.enable_starttls && !self.session.tls => {
+                if let Some(tls_config) = self.handler.tls_request(self.session).await {
                     return Ok(Some(LoopExit::STARTTLS(tls_config)));
                 } else {
-                    socket.send(Reply::not_implemented()).await?;
+                    self.send_reply(socket, Reply::not_implemented()).await?;
                 }
             }
             Ext(crate::Ext::BDAT(size, last)) if self.config.enable_chunking => {
-                let reply = self.do_bdat(socket, size, last).await?;
-                socket.send(reply).await?;
+                let responses = self.do_bdat(socket, size, last).await?;
+                return self.respond_all(socket, responses, false).await;
+            }
+            Ext(crate::Ext::XFORWARD(params)) if self.extensions.contains_key("XFORWARD") => {
+                let response = self.do_xforward(params).await?;
+                return self.respond(socket, response, false).await;
+            }
+            Ext(crate::Ext::XCLIENT(params)) if self.extensions.contains_key("XCLIENT") => {
+                let response = self.do_xclient(params).await?;
+                return self.respond(socket, response, true).await;
+            }
+            Ext(crate::Ext::AUTH(mechanism, initial))
+                if !self.config.auth_mechanisms.is_empty() =>
+            {
+                let reply = self.do_auth(socket, mechanism, initial).await?;
+                self.send_reply(socket, reply).await?;
             }
             _ => {
-                let reply = self
-                    .handler
-                    .unhandled_command(command)
-                    .await
-                    .unwrap_or_else(Reply::not_implemented);
-                socket.send(reply).await?;
+                let noop = matches!(command, Base(NOOP(_)));
+                let response = match self.handler.unhandled_command(self.session, command).await {
+                    Some(response) => response,
+                    None if noop => Reply::not_implemented().into(),
+                    None => {
+                        self.count_error(socket, ErrorCount::UnknownCommands)
+                            .await?;
+                        Reply::not_implemented().into()
+                    }
+                };
+                return self.respond(socket, response, true).await;
             }
         }
         Ok(None)
     }
 
-    async fn do_ehlo(&mut self, domain: DomainPart) -> Result<Reply, ServerError> {
-        let mut initial_keywords = EhloKeywords::new();
-        for kw in ["PIPELINING", "ENHANCEDSTATUSCODES"].as_ref() {
-            initial_keywords.insert((*kw).into(), None);
-        }
-        if self.config.enable_smtputf8 {
-            initial_keywords.insert("8BITMIME".into(), None);
-            initial_keywords.insert("SMTPUTF8".into(), None);
-        }
-        if self.config.enable_chunking {
-            initial_keywords.insert("CHUNKING".into(), None);
+    /// Send the reply of `response`, then apply its action.
+    async fn respond<S>(
+        &mut self,
+        socket: &mut S,
+        response: Response,
+        flush: bool,
+    ) -> Result<Option<LoopExit<H>>, ServerError>
+    where
+        S: Sink<Reply> + Unpin,
+        ServerError: From<<S as Sink<Reply>>::Error>,
+    {
+        let Response { reply, action } = response;
+
+        match action {
+            Action::Disconnect => {
+                self.send_reply(socket, reply).await?;
+                return Ok(Some(LoopExit::Disconnect));
+            }
+            _ if flush => self.send_reply(socket, reply).await?,
+            _ => self.feed_reply(socket, reply).await?,
         }
-        if self.config.enable_starttls {
-            initial_keywords.insert("STARTTLS".into(), None);
+        if action == Action::ResetTransaction {
+            self.end_transaction(TransactionOutcome::Aborted).await;
         }
+        Ok(None)
+    }
 
-        match self.handler.ehlo(domain, initial_keywords).await {
-            Err(reply) => Ok(reply),
-            Ok((greeting, keywords)) => {
-                assert!(!greeting.contains('\r') && !greeting.contains('\n'));
+    /// Send the replies to a message, one per recipient with LMTP,
+    /// then apply the strongest of their actions.
+    async fn respond_all<S>(
+        &mut self,
+        socket: &mut S,
+        responses: Vec<Response>,
+        flush: bool,
+    ) -> Result<Option<LoopExit<H>>, ServerError>
+    where
+        S: Sink<Reply> + Unpin,
+        ServerError: From<<S as Sink<Reply>>::Error>,
+    {
+        let mut action = Action::Continue;
+        let mut responses = responses.into_iter().peekable();
+
+        while let Some(response) = responses.next() {
+            action = match (action, response.action) {
+                (Action::Disconnect, _) | (_, Action::Continue) => action,
+                (_, other) => other,
+            };
+            if responses.peek().is_some() {
+                self.feed_reply(socket, response.reply).await?;
+            } else {
+                let last = Response::new(response.reply, action);
+                return self.respond(socket, last, flush).await;
+            }
+        }
+        Ok(None)
+    }
+
+    fn transactions_exceeded(&self) -> bool {
+        matches!(self.state, State::Initial)
+            && self
+                .config
+                .limits
+                .max_transactions
+                .is_some_and(|max| self.session.transactions >= max)
+    }
+
+    fn recipients_exceeded(&self) -> bool {
+        self.config
+            .limits
+            .max_recipients
+            .is_some_and(|max| self.envelope.recipients.len() >= max)
+    }
+
+    async fn do_ehlo(&mut self, domain: DomainPart) -> Result<Response, ServerError> {
+        let mut initial_keywords = EhloKeywords::new();
+        for kw in ["PIPELINING", "ENHANCEDSTATUSCODES"].as_ref() {
+            initial_keywords.insert((*kw).into(), None);
+        }
+        if self.config.enable_smtputf8 {
+            initial_keywords.insert("8BITMIME".into(), None);
+            initial_keywords.insert("SMTPUTF8".into(), None);
+        }
+        if self.config.enable_chunking {
+            initial_keywords.insert("CHUNKING".into(), None);
+        }
+        if self.config.enable_starttls && !self.session.tls {
+            initial_keywords.insert("STARTTLS".into(), None);
+        }
+        if !self.config.auth_mechanisms.is_empty() {
+            initial_keywords.insert("AUTH".into(), Some(self.config.auth_mechanisms.join(" ")));
+        }
+        if let Some(max_size) = self.config.max_message_size {
+            initial_keywords.insert("SIZE".into(), Some(max_size.to_string()));
+        }
+        if self.config.enable_dsn {
+            initial_keywords.insert("DSN".into(), None);
+        }
+        if self.handler.xclient_allowed(self.session).await {
+            initial_keywords.insert("XCLIENT".into(), Some(XCLIENT_ATTRIBUTES.into()));
+        }
+        if self.handler.xforward_allowed(self.session).await {
+            initial_keywords.insert("XFORWARD".into(), Some(XFORWARD_ATTRIBUTES.into()));
+        }
+
+        match self
+            .handler
+            .ehlo(self.session, domain.clone(), initial_keywords)
+            .await
+        {
+            Err(response) => Ok(response),
+            Ok((greeting, keywords)) => {
+                assert!(!greeting.contains('\r') && !greeting.contains('\n'));
                 let mut reply_text = format!("{}\n", greeting);
 
-                for (kw, value) in keywords {
+                for (kw, value) in &keywords {
                     match value {
                         Some(value) => writeln!(reply_text, "{} {}", kw, value).unwrap(),
                         None => writeln!(reply_text, "{}", kw).unwrap(),
                     }
                 }
-                self.state = State::Initial;
-                Ok(Reply::new(250, None, reply_text))
+                self.extensions = keywords
+                    .into_iter()
+                    .map(|(kw, value)| (kw.to_ascii_uppercase(), value))
+                    .collect();
+                self.greeted = true;
+                self.session.helo = Some(domain);
+                self.end_transaction(TransactionOutcome::Aborted).await;
+                Ok(Reply::new(250, None, reply_text).into())
             }
         }
     }
 
-    async fn do_helo(&mut self, domain: Domain) -> Result<Reply, ServerError> {
+    async fn do_helo(&mut self, domain: Domain) -> Result<Response, ServerError> {
         Ok(
-            match self.handler.helo(domain).await.with_default(Reply::ok()) {
-                Ok(reply) => {
-                    self.state = State::Initial;
-                    reply
+            match self
+                .handler
+                .helo(self.session, domain.clone())
+                .await
+                .with_default(Reply::ok())
+            {
+                Ok(response) => {
+                    // No extensions with HELO.
+                    self.extensions.clear();
+                    self.greeted = true;
+                    self.session.helo = Some(domain.into());
+                    self.end_transaction(TransactionOutcome::Aborted).await;
+                    response
                 }
-                Err(reply) => reply,
+                Err(response) => response,
             },
         )
     }
@@ -342,21 +1236,51 @@ where
         &mut self,
         path: ReversePath,
         params: Vec<Param>,
-    ) -> Result<Reply, ServerError> {
+    ) -> Result<Response, ServerError> {
         Ok(match self.state {
-            State::Initial => match self
-                .handler
-                .mail(path, params)
-                .await
-                .with_default(Reply::ok())
-            {
-                Ok(reply) => {
-                    self.state = State::MAIL;
-                    reply
+            State::Initial if !self.greeted && !self.config.lenient_greeting => {
+                Reply::new(503, Some(EnhancedCode(5, 5, 1)), "Send HELO or EHLO first").into()
+            }
+            State::Initial => {
+                let mut params = match MailParams::parse(params, &self.extensions) {
+                    Ok(params) => params,
+                    Err(reply) => return Ok(reply.into()),
+                };
+
+                if let (Some(size), Some(max_size)) = (params.size, self.config.max_message_size) {
+                    if size > max_size {
+                        return Ok(Reply::message_too_large().into());
+                    }
                 }
-                Err(reply) => reply,
-            },
-            _ => Reply::bad_sequence(),
+                // RFC 4954 section 5: behave as if AUTH=<> was
+                // supplied for unauthenticated clients.
+                if params.auth.is_some() && self.session.authenticated.is_none() {
+                    params.auth = Some("<>".into());
+                }
+                let dsn = if self.extensions.contains_key("DSN") {
+                    Some(DsnRequest::from_mail(&params))
+                } else {
+                    None
+                };
+
+                match self
+                    .handler
+                    .mail(self.session, path.clone(), params.clone())
+                    .await
+                    .with_default(Reply::ok())
+                {
+                    Ok(response) => {
+                        self.state = State::MAIL;
+                        self.session.transactions += 1;
+                        self.envelope.reverse_path = Some(path);
+                        self.envelope.mail_params = params;
+                        self.envelope.dsn = dsn;
+                        response
+                    }
+                    Err(response) => response,
+                }
+            }
+            _ => Reply::bad_sequence().into(),
         })
     }
 
@@ -364,70 +1288,162 @@ where
         &mut self,
         path: ForwardPath,
         params: Vec<Param>,
-    ) -> Result<Reply, ServerError> {
+    ) -> Result<Response, ServerError> {
         Ok(match self.state {
-            State::MAIL | State::RCPT => match self
-                .handler
-                .rcpt(path, params)
-                .await
-                .with_default(Reply::ok())
-            {
-                Ok(reply) => {
-                    self.state = State::RCPT;
-                    reply
+            State::MAIL | State::RCPT => {
+                let params = match RcptParams::parse(params, &self.extensions) {
+                    Ok(params) => params,
+                    Err(reply) => return Ok(reply.into()),
+                };
+                let dsn = self
+                    .envelope
+                    .dsn
+                    .as_ref()
+                    .map(|_| DsnRequest::recipient(&path, &params));
+
+                match self
+                    .handler
+                    .rcpt(self.session, path.clone(), params.clone())
+                    .await
+                    .with_default(Reply::ok())
+                {
+                    Ok(response) => {
+                        self.state = State::RCPT;
+                        self.envelope
+                            .recipients
+                            .push(EnvelopeRecipient { path, params });
+                        if let (Some(request), Some(dsn)) = (&mut self.envelope.dsn, dsn) {
+                            request.add_recipient(dsn);
+                        }
+                        response
+                    }
+                    Err(response) => response,
                 }
-                Err(reply) => reply,
-            },
-            _ => Reply::bad_sequence(),
+            }
+            _ => Reply::bad_sequence().into(),
         })
     }
 
-    async fn do_data<S>(&mut self, socket: &mut S) -> Result<Reply, ServerError>
+    async fn do_data<S>(
+        &mut self,
+        socket: &mut Framed<S, LineCodec>,
+    ) -> Result<Vec<Response>, ServerError>
     where
-        S: Stream<Item = Result<BytesMut, LineError>> + Unpin + Send,
-        S: Sink<Reply>,
-        ServerError: From<<S as Sink<Reply>>::Error>,
+        Framed<S, LineCodec>: Stream<Item = Result<BytesMut, LineError>>
+            + Sink<Reply, Error = LineError>
+            + Send
+            + Unpin,
     {
-        Ok(match self.state {
+        Ok(vec![match self.state {
             State::RCPT => match self
                 .handler
-                .data_start()
+                .data_start(self.session, &self.envelope)
                 .await
                 .with_default(Reply::data_ok())
             {
-                Ok(reply) => {
-                    socket.send(reply).await?;
+                Ok(response) => {
+                    self.send_reply(socket, response.reply).await?;
 
-                    let mut body_stream = read_body_data(socket).fuse();
-                    let mut reply = self
-                        .handler
-                        .data(&mut body_stream)
-                        .await?
-                        .unwrap_or_else(Reply::ok);
+                    let config = self.config;
+                    let timeouts = &config.timeouts;
+                    self.deadline.data(
+                        timeouts.data_initiation,
+                        timeouts.data_block,
+                        timeouts.min_data_rate,
+                    );
 
-                    if !body_stream.is_done() {
+                    let ended = Arc::new(AtomicBool::new(false));
+                    let exceeded = Arc::new(AtomicBool::new(false));
+                    let too_long = Arc::new(AtomicBool::new(false));
+                    let bare_newlines = Arc::new(Mutex::new(Vec::new()));
+                    let body = check_bare_newlines(
+                        stop_at_long_line(read_body_data(socket, ended.clone()), too_long.clone()),
+                        self.config.bare_newline,
+                        bare_newlines.clone(),
+                    );
+                    let body = limit_body_size(
+                        unstuff_body(body),
+                        config.max_message_size,
+                        exceeded.clone(),
+                    );
+                    let mut body_stream = self
+                        .deadline
+                        .then_processing(body, timeouts.data_termination)
+                        .fuse();
+                    let deadline = self.deadline.clone();
+                    let res = deadline
+                        .bounded(async {
+                            if config.unified_message {
+                                self.deliver_message(&mut body_stream).await
+                            } else if config.lmtp {
+                                self.handler
+                                    .lmtp_data(self.session, &mut body_stream, &self.envelope)
+                                    .await
+                            } else {
+                                self.handler
+                                    .data(self.session, &mut body_stream, &self.envelope)
+                                    .await
+                                    .map(|response| {
+                                        vec![response.unwrap_or_else(|| Reply::ok().into())]
+                                    })
+                            }
+                        })
+                        .await?;
+
+                    // The handler result is irrelevant once the
+                    // message is rejected, the rest of the body is
+                    // discarded.
+                    let rejection = if exceeded.load(Ordering::SeqCst) {
+                        Some(Reply::message_too_large())
+                    } else if too_long.load(Ordering::SeqCst) {
+                        Some(Reply::message_line_too_long())
+                    } else if self.config.bare_newline == BareNewlinePolicy::Reject
+                        && !bare_newlines.lock().unwrap().is_empty()
+                    {
+                        Some(Reply::bare_newline())
+                    } else {
+                        None
+                    };
+
+                    if let Some(reply) = rejection {
                         drop(body_stream);
-                        // The handler MUST signal an error.
-                        if !reply.is_error() {
-                            reply = Reply::data_abort();
+                        // The end of data may have been read while
+                        // looking past the offending line.
+                        if !ended.load(Ordering::SeqCst) {
+                            drain(read_body_data(socket, ended)).await?;
                         }
 
-                        socket.send(reply).await?;
+                        let responses = self.final_responses(reply.into());
+                        self.report_bare_newlines(&bare_newlines).await;
+                        self.end_transaction(TransactionOutcome::Aborted).await;
+                        return Ok(responses);
+                    }
+
+                    let mut responses = res?;
+                    if !body_stream.is_done() {
+                        drop(body_stream);
+                        self.send_reply(socket, abort_reply(responses)).await?;
 
                         return Err(ServerError::DataAbort);
                     }
 
-                    self.state = State::Initial;
-                    reply
+                    if self.config.lmtp {
+                        responses.resize_with(self.envelope.recipients.len(), || {
+                            Reply::data_abort().into()
+                        });
+                    }
+                    self.report_bare_newlines(&bare_newlines).await;
+                    self.end_transaction(outcome(&responses)).await;
+                    return Ok(responses);
                 }
-                Err(reply) => reply,
+                Err(response) => response,
             },
-            State::Initial => Reply::no_mail_transaction(),
-            State::MAIL => Reply::no_valid_recipients(),
+            State::Initial => Reply::no_mail_transaction().into(),
+            State::MAIL => Reply::no_valid_recipients().into(),
             State::BDAT | State::BDATFAIL => {
-                Reply::new(503, None, "BDAT may not be mixed with DATA")
+                Reply::new(503, None, "BDAT may not be mixed with DATA").into()
             }
-        })
+        }])
     }
 
     async fn do_bdat<S>(
@@ -435,54 +1451,390 @@ where
         socket: &mut Framed<S, LineCodec>,
         chunk_size: u64,
         last: bool,
-    ) -> Result<Reply, ServerError>
+    ) -> Result<Vec<Response>, ServerError>
     where
         Framed<S, LineCodec>: Stream<Item = Result<BytesMut, LineError>>
             + Sink<Reply, Error = LineError>
             + Send
             + Unpin,
     {
-        Ok(match self.state {
-            State::RCPT | State::BDAT => {
-                let mut body_stream = read_body_bdat(socket, chunk_size).fuse();
+        let timeouts = &self.config.timeouts;
+        let termination = timeouts.data_termination;
+        let deadline = self.deadline.clone();
+        deadline.data(
+            timeouts.data_block,
+            timeouts.data_block,
+            timeouts.min_data_rate,
+        );
 
-                let reply = self
-                    .handler
-                    .bdat(&mut body_stream, chunk_size, last)
-                    .await?;
+        Ok(vec![match self.state {
+            State::RCPT | State::BDAT if self.exceeds_max_size(chunk_size) => {
+                drain(read_body_bdat(socket, chunk_size)?).await?;
+                self.state = State::BDATFAIL;
 
-                if !body_stream.is_done() {
-                    let mut reply = reply.unwrap_or_else(Reply::ok);
+                if last {
+                    let responses = self.final_responses(Reply::message_too_large().into());
+                    self.end_transaction(TransactionOutcome::Aborted).await;
+                    return Ok(responses);
+                }
+                Reply::message_too_large().into()
+            }
+            State::RCPT | State::BDAT if self.config.unified_message => {
+                let config = self.config;
+                let end = Arc::new(Mutex::new(None));
+                let body = read_message_bdat(
+                    socket,
+                    chunk_size,
+                    last,
+                    config,
+                    deadline.clone(),
+                    end.clone(),
+                )?;
+                let mut body_stream = deadline.then_processing(body, termination).fuse();
 
-                    drop(body_stream);
-                    // The handler MUST signal an error.
-                    if !reply.is_error() {
-                        reply = Reply::data_abort();
+                let responses = deadline
+                    .bounded(self.deliver_message(&mut body_stream))
+                    .await??;
+
+                // The handler may stop at the error ending the body, the
+                // socket is only out of step when no end was reached.
+                let end = end.lock().unwrap().take();
+                let end = match end {
+                    Some(end) => end,
+                    None => {
+                        drop(body_stream);
+                        self.send_reply(socket, abort_reply(responses)).await?;
+
+                        return Err(ServerError::DataAbort);
                     }
+                };
 
-                    socket.send(reply).await?;
+                return match end {
+                    ChunksEnd::Last => {
+                        self.end_transaction(outcome(&responses)).await;
+                        Ok(responses)
+                    }
+                    ChunksEnd::TooLarge { last: true } => {
+                        let responses = self.final_responses(Reply::message_too_large().into());
+                        self.end_transaction(TransactionOutcome::Aborted).await;
+                        Ok(responses)
+                    }
+                    ChunksEnd::TooLarge { last: false } => {
+                        self.state = State::BDATFAIL;
+                        Ok(vec![Reply::message_too_large().into()])
+                    }
+                    // The message is abandoned like a failed chunk, the
+                    // command is served as usual.
+                    ChunksEnd::Interrupted(line) => {
+                        self.state = State::BDATFAIL;
+                        self.interrupted = Some(line);
+                        Ok(Vec::new())
+                    }
+                    ChunksEnd::Failed(e) => Err(e),
+                };
+            }
+            State::RCPT | State::BDAT if last && self.config.lmtp => {
+                self.message_size += chunk_size;
+                let body = read_body_bdat(socket, chunk_size)?;
+                let mut body_stream = deadline.then_processing(body, termination).fuse();
+
+                let mut responses = deadline
+                    .bounded(self.handler.lmtp_bdat(
+                        self.session,
+                        &mut body_stream,
+                        chunk_size,
+                        &self.envelope,
+                    ))
+                    .await??;
+
+                if !body_stream.is_done() {
+                    drop(body_stream);
+                    self.send_reply(socket, abort_reply(responses)).await?;
 
                     return Err(ServerError::DataAbort);
                 }
 
-                match reply.with_default(Reply::ok()) {
-                    Ok(reply) => {
+                responses.resize_with(self.envelope.recipients.len(), || {
+                    Reply::data_abort().into()
+                });
+                self.end_transaction(outcome(&responses)).await;
+                return Ok(responses);
+            }
+            State::RCPT | State::BDAT => {
+                self.message_size += chunk_size;
+                let body = read_body_bdat(socket, chunk_size)?;
+                let mut body_stream = deadline.then_processing(body, termination).fuse();
+
+                let response = deadline
+                    .bounded(self.handler.bdat(
+                        self.session,
+                        &mut body_stream,
+                        chunk_size,
+                        last,
+                        &self.envelope,
+                    ))
+                    .await??;
+
+                if !body_stream.is_done() {
+                    drop(body_stream);
+                    self.send_reply(socket, abort_reply(response)).await?;
+
+                    return Err(ServerError::DataAbort);
+                }
+
+                match response.with_default(Reply::ok()) {
+                    Ok(response) => {
                         if last {
-                            self.state = State::Initial
+                            self.end_transaction(TransactionOutcome::Committed).await;
                         } else {
                             self.state = State::BDAT
                         }
-                        reply
+                        response
                     }
-                    Err(reply) => {
+                    Err(response) if last => {
+                        self.end_transaction(TransactionOutcome::Aborted).await;
+                        response
+                    }
+                    Err(response) => {
                         self.state = State::BDATFAIL;
-                        reply
+                        response
                     }
                 }
             }
-            State::MAIL => Reply::no_valid_recipients(),
-            _ => Reply::no_mail_transaction(),
-        })
+            State::MAIL => {
+                drain(read_body_bdat(socket, chunk_size)?).await?;
+                Reply::no_valid_recipients().into()
+            }
+            // The rest of a failed message, it ends with the last
+            // chunk.
+            State::BDATFAIL => {
+                drain(read_body_bdat(socket, chunk_size)?).await?;
+                if last {
+                    self.end_transaction(TransactionOutcome::Aborted).await;
+                }
+                Reply::no_mail_transaction().into()
+            }
+            _ => {
+                drain(read_body_bdat(socket, chunk_size)?).await?;
+                Reply::no_mail_transaction().into()
+            }
+        }])
+    }
+
+    fn banner(&self) -> Reply {
+        let protocol = if self.config.lmtp { "LMTP" } else { "ESMTP" };
+        Reply::new(
+            220,
+            None,
+            format!("{} {} smtpbis 0.1.0", self.config.hostname, protocol),
+        )
+    }
+
+    async fn do_xclient(&mut self, params: Vec<XclientParam>) -> Result<Response, ServerError> {
+        if self.state != State::Initial {
+            return Ok(Reply::new(
+                503,
+                Some(EnhancedCode(5, 5, 1)),
+                "Mail transaction in progress",
+            )
+            .into());
+        }
+        let mut xclient = self.session.xclient.clone().unwrap_or_default();
+        if let Err(reply) = xclient.update(params) {
+            return Ok(reply.into());
+        }
+        if let Some(response) = self.handler.xclient(self.session, &xclient).await {
+            return Ok(response);
+        }
+
+        // The session restarts as if the proxied client had just
+        // connected, so a new EHLO is required.
+        self.reset_transaction();
+        self.extensions.clear();
+        self.greeted = false;
+        self.session.helo = None;
+        self.session.authenticated = xclient.login.clone();
+        override_addr(&mut self.session.peer_addr, xclient.addr, xclient.port);
+        override_addr(
+            &mut self.session.local_addr,
+            xclient.destaddr,
+            xclient.destport,
+        );
+        self.session.xclient = Some(xclient);
+
+        Ok(self.banner().into())
+    }
+
+    async fn do_xforward(&mut self, params: Vec<XforwardParam>) -> Result<Response, ServerError> {
+        if self.state != State::Initial {
+            return Ok(Reply::new(
+                503,
+                Some(EnhancedCode(5, 5, 1)),
+                "Mail transaction in progress",
+            )
+            .into());
+        }
+        if let Err(reply) = self.envelope.xforward.update(params) {
+            return Ok(reply.into());
+        }
+
+        Ok(self
+            .handler
+            .xforward(self.session, &self.envelope.xforward)
+            .await
+            .unwrap_or_else(|| Reply::ok().into()))
+    }
+
+    fn exceeds_max_size(&self, chunk_size: u64) -> bool {
+        self.config
+            .max_message_size
+            .is_some_and(|max| self.message_size.saturating_add(chunk_size) > max)
+    }
+
+    /// Pass the bare newlines found in a message body to the handler.
+    async fn report_bare_newlines(&mut self, found: &Mutex<Vec<BareNewline>>) {
+        let found = std::mem::take(&mut *found.lock().unwrap());
+
+        for bare in &found {
+            self.handler.bare_newline(self.session, bare).await;
+        }
+    }
+
+    /// Pass the whole message to [`Handler::message`].
+    async fn deliver_message<S>(&mut self, stream: &mut S) -> Result<Vec<Response>, ServerError>
+    where
+        S: Stream<Item = Result<BytesMut, LineError>> + Unpin + Send,
+    {
+        let response = self
+            .handler
+            .message(self.session, stream, &self.envelope)
+            .await?;
+        Ok(self.final_responses(response.unwrap_or_else(|| Reply::ok().into())))
+    }
+
+    /// Replies at the end of a message: one per recipient with LMTP.
+    fn final_responses(&self, response: Response) -> Vec<Response> {
+        if self.config.lmtp {
+            vec![response; self.envelope.recipients.len()]
+        } else {
+            vec![response]
+        }
+    }
+
+    /// Report the end of the current transaction, if any, and reset
+    /// it.
+    async fn end_transaction(&mut self, outcome: TransactionOutcome) {
+        if self.state != State::Initial {
+            self.handler
+                .transaction_end(self.session, &self.envelope, outcome)
+                .await;
+        }
+        self.reset_transaction();
+    }
+
+    fn reset_transaction(&mut self) {
+        self.state = State::Initial;
+        self.message_size = 0;
+        self.envelope = Envelope::default();
+    }
+
+    async fn do_auth<S>(
+        &mut self,
+        socket: &mut Framed<S, LineCodec>,
+        mechanism: String,
+        initial: Option<String>,
+    ) -> Result<Reply, ServerError>
+    where
+        Framed<S, LineCodec>: Stream<Item = Result<BytesMut, LineError>>
+            + Sink<Reply, Error = LineError>
+            + Send
+            + Unpin,
+    {
+        if self.session.authenticated.is_some() {
+            return Ok(Reply::new(
+                503,
+                Some(EnhancedCode(5, 5, 1)),
+                "Already authenticated",
+            ));
+        }
+        if self.state != State::Initial {
+            return Ok(Reply::new(
+                503,
+                Some(EnhancedCode(5, 5, 1)),
+                "AUTH not permitted during a mail transaction",
+            ));
+        }
+
+        let advertised = self
+            .config
+            .auth_mechanisms
+            .iter()
+            .any(|m| m.eq_ignore_ascii_case(&mechanism));
+        let mut sasl =
+            match self
+                .handler
+                .sasl_mechanism(self.session, &mechanism, &self.config.hostname)
+            {
+                Some(sasl) if advertised => sasl,
+                _ => {
+                    return Ok(Reply::new(
+                        504,
+                        Some(EnhancedCode(5, 5, 4)),
+                        "Unrecognized authentication type",
+                    ))
+                }
+            };
+
+        let mut response = match initial.as_deref() {
+            None => None,
+            Some("=") => Some(Vec::new()),
+            Some(encoded) => match base64::decode(encoded) {
+                Ok(decoded) => Some(decoded),
+                Err(_) => return Ok(Reply::auth_malformed()),
+            },
+        };
+
+        loop {
+            match sasl.step(response.as_deref()) {
+                SaslStep::Challenge(challenge) => {
+                    socket
+                        .send(Reply::new(334, None, base64::encode(&challenge)))
+                        .await?;
+
+                    self.deadline.command(self.config.timeouts.mail);
+                    socket.codec_mut().sasl_mode();
+                    let line = match socket.next().await.ok_or(ServerError::EOF)? {
+                        Err(LineError::LineTooLong) => return Ok(Reply::line_too_long()),
+                        res => res?,
+                    };
+                    let line = line.strip_suffix(b"\r\n").unwrap_or(&line);
+
+                    if line == b"*" {
+                        return Ok(Reply::new(
+                            501,
+                            Some(EnhancedCode(5, 0, 0)),
+                            "Authentication cancelled",
+                        ));
+                    }
+                    response = match base64::decode(line) {
+                        Ok(decoded) => Some(decoded),
+                        Err(_) => return Ok(Reply::auth_malformed()),
+                    };
+                }
+                SaslStep::Done(credentials) => {
+                    return Ok(
+                        match self.handler.authenticate(self.session, credentials).await {
+                            Ok(identity) => {
+                                self.session.authenticated = Some(identity);
+                                Reply::auth_ok()
+                            }
+                            Err(reply) => reply,
+                        },
+                    );
+                }
+                SaslStep::Failed(reply) => return Ok(reply),
+            }
+        }
     }
 }
 
@@ -495,6 +1847,13 @@ pub enum ServerError {
     Pipelining,
     DataAbort,
     Shutdown,
+    /// Missing or invalid PROXY protocol header.
+    ProxyProtocol,
+    /// A limit from [`Config::timeouts`] expired, 421 was sent.
+    Timeout,
+    /// A hard limit from [`Config::limits`] was reached, 421 was
+    /// sent.
+    LimitExceeded,
 }
 
 impl From<LineError> for ServerError {
@@ -512,23 +1871,78 @@ impl From<std::io::Error> for ServerError {
     }
 }
 
-fn read_body_data<'a, S>(source: &'a mut S) -> impl Stream<Item = Result<BytesMut, LineError>> + 'a
+/// A message is committed once accepted for any recipient.
+fn outcome(responses: &[Response]) -> TransactionOutcome {
+    if responses.iter().any(|response| response.reply.is_success()) {
+        TransactionOutcome::Committed
+    } else {
+        TransactionOutcome::Aborted
+    }
+}
+
+/// Reply to a message the handler did not read to the end, which
+/// MUST be an error.
+fn abort_reply<I: IntoIterator<Item = Response>>(responses: I) -> Reply {
+    responses
+        .into_iter()
+        .map(|response| response.reply)
+        .find(Reply::is_error)
+        .unwrap_or_else(Reply::data_abort)
+}
+
+/// Apply an address and port overridden by XCLIENT.
+fn override_addr(current: &mut Option<SocketAddr>, ip: Option<IpAddr>, port: Option<u16>) {
+    match (ip, port, current.as_mut()) {
+        (Some(ip), port, current_addr) => {
+            let port = port.or_else(|| current_addr.map(|a| a.port())).unwrap_or(0);
+            *current = Some(SocketAddr::new(ip, port));
+        }
+        (None, Some(port), Some(current)) => current.set_port(port),
+        _ => (),
+    }
+}
+
+/// Name of `command` if it must end a pipelined group (RFC 2920
+/// 3.1), for [`PipeliningViolation::AfterGroupEnd`].
+fn group_end(command: &Command) -> Option<&'static str> {
+    match command {
+        Base(EHLO(_)) => Some("EHLO"),
+        Base(HELO(_)) => Some("HELO"),
+        Ext(crate::Ext::LHLO(_)) => Some("LHLO"),
+        Base(DATA) => Some("DATA"),
+        Base(QUIT) => Some("QUIT"),
+        Base(NOOP(_)) => Some("NOOP"),
+        Base(VRFY(_)) => Some("VRFY"),
+        Base(EXPN(_)) => Some("EXPN"),
+        Ext(crate::Ext::AUTH(..)) => Some("AUTH"),
+        Ext(crate::Ext::BDAT(_, true)) => Some("BDAT LAST"),
+        _ => None,
+    }
+}
+
+/// Read DATA body lines, setting `ended` once the end of data line
+/// is read.
+fn read_body_data<'a, S>(
+    socket: &'a mut Framed<S, LineCodec>,
+    ended: Arc<AtomicBool>,
+) -> impl Stream<Item = Result<BytesMut, LineError>> + 'a
 where
-    S: Stream<Item = Result<BytesMut, LineError>> + Unpin,
+    Framed<S, LineCodec>: Stream<Item = Result<BytesMut, LineError>> + Unpin,
 {
-    let gen_abort = Arc::new(AtomicBool::new(true));
-    let gen_abort2 = gen_abort.clone();
+    let ended2 = ended.clone();
 
     let abort = futures::stream::once(ready(Err(LineError::DataAbort)))
-        .filter(move |_| ready(gen_abort.load(Ordering::SeqCst)));
+        .filter(move |_| ready(!ended.load(Ordering::SeqCst)));
 
-    source
+    socket.codec_mut().data_mode();
+
+    socket
         .take_while(move |res| {
             ready(
                 res.as_ref()
                     .map(|line| {
                         if line.as_ref() == b".\r\n" {
-                            gen_abort2.store(false, Ordering::SeqCst);
+                            ended2.store(true, Ordering::SeqCst);
                             false
                         } else {
                             true
@@ -537,21 +1951,291 @@ where
                     .unwrap_or(true),
             )
         })
-        .map(|res| {
-            res.map(|mut line| {
-                if line.starts_with(b".") {
-                    line.advance(1);
+        .chain(abort)
+}
+
+/// End a body stream after a `LineError::LineTooLong`, setting
+/// `too_long`.
+fn stop_at_long_line<'a, S>(
+    source: S,
+    too_long: Arc<AtomicBool>,
+) -> impl Stream<Item = Result<BytesMut, LineError>> + 'a
+where
+    S: Stream<Item = Result<BytesMut, LineError>> + 'a,
+{
+    source.scan((), move |_, res| {
+        if too_long.load(Ordering::SeqCst) {
+            return ready(None);
+        }
+        if let Err(LineError::LineTooLong) = res {
+            too_long.store(true, Ordering::SeqCst);
+        }
+        ready(Some(res))
+    })
+}
+
+/// Remove the leading dot from dot-stuffed body lines.
+fn unstuff_body<S>(source: S) -> impl Stream<Item = Result<BytesMut, LineError>>
+where
+    S: Stream<Item = Result<BytesMut, LineError>>,
+{
+    source.map(|res| {
+        res.map(|mut line| {
+            // A lone dot can only come from a normalized bare newline,
+            // it has no other characters to unstuff.
+            if line.starts_with(b".") && &line[..] != b".\r\n" {
+                line.advance(1);
+            }
+            line
+        })
+    })
+}
+
+/// Apply `policy` to the bare CR and LF in dot-stuffed body lines,
+/// recording them in `found`. Normalized lines are split so each
+/// part is unstuffed on its own.
+///
+/// When rejecting, a `LineError::BareNewline` is yielded before the
+/// stream ends.
+fn check_bare_newlines<'a, S>(
+    source: S,
+    policy: BareNewlinePolicy,
+    found: Arc<Mutex<Vec<BareNewline>>>,
+) -> impl Stream<Item = Result<BytesMut, LineError>> + 'a
+where
+    S: Stream<Item = Result<BytesMut, LineError>> + 'a,
+{
+    source
+        .scan(0u64, move |number, res| {
+            let mut found = found.lock().unwrap();
+            if policy == BareNewlinePolicy::Reject && !found.is_empty() {
+                return ready(None);
+            }
+            let line = match res {
+                Ok(line) => line,
+                Err(e) => return ready(Some(vec![Err(e)])),
+            };
+
+            *number += 1;
+            let bare = find_bare_newlines(&line, *number);
+            if bare.is_empty() {
+                return ready(Some(vec![Ok(line)]));
+            }
+            let room = MAX_BARE_NEWLINE_REPORTS.saturating_sub(found.len());
+            found.extend(bare.into_iter().take(room));
+
+            ready(Some(match policy {
+                BareNewlinePolicy::Reject => vec![Err(LineError::BareNewline)],
+                BareNewlinePolicy::Normalize => {
+                    normalize_newlines(&line).into_iter().map(Ok).collect()
                 }
-                line
-            })
+                BareNewlinePolicy::PassThrough => vec![Ok(line)],
+            }))
         })
-        .chain(abort)
+        .flat_map(stream::iter)
+}
+
+/// Cut a body stream short once more than `limit` bytes were read.
+///
+/// A `LineError::MessageTooLarge` is yielded before the stream ends
+/// and `exceeded` is set.
+fn limit_body_size<'a, S>(
+    source: S,
+    limit: Option<u64>,
+    exceeded: Arc<AtomicBool>,
+) -> impl Stream<Item = Result<BytesMut, LineError>> + 'a
+where
+    S: Stream<Item = Result<BytesMut, LineError>> + 'a,
+{
+    let limit = limit.unwrap_or(u64::MAX);
+
+    source.scan(0u64, move |total, res| {
+        if exceeded.load(Ordering::SeqCst) {
+            return ready(None);
+        }
+        if let Ok(line) = &res {
+            *total = total.saturating_add(line.len() as u64);
+            if *total > limit {
+                exceeded.store(true, Ordering::SeqCst);
+                return ready(Some(Err(LineError::MessageTooLarge)));
+            }
+        }
+        ready(Some(res))
+    })
+}
+
+/// Read and discard the rest of a body stream, including over-long
+/// lines.
+async fn drain<S>(stream: S) -> Result<(), LineError>
+where
+    S: Stream<Item = Result<BytesMut, LineError>> + Unpin,
+{
+    stream
+        .filter(|res| ready(!matches!(res, Err(LineError::LineTooLong))))
+        .try_for_each(|_| ready(Ok(())))
+        .await
+}
+
+/// How the body of a unified BDAT message ended.
+enum ChunksEnd {
+    /// The chunk marked LAST was read.
+    Last,
+    /// A chunk would exceed [`Config::max_message_size`], it was
+    /// discarded.
+    TooLarge {
+        last: bool,
+    },
+    /// Another command came in place of the next BDAT.
+    Interrupted(Result<BytesMut, LineError>),
+    Failed(ServerError),
+}
+
+/// Reader of the chunks of a unified BDAT message.
+struct ChunkReader<'a, S> {
+    socket: &'a mut Framed<S, LineCodec>,
+    /// Size of the chunks announced so far.
+    size: u64,
+    last: bool,
+    config: &'a Config,
+    deadline: Deadline,
+    end: Arc<Mutex<Option<ChunksEnd>>>,
+}
+
+impl<'a, S> ChunkReader<'a, S>
+where
+    Framed<S, LineCodec>:
+        Stream<Item = Result<BytesMut, LineError>> + Sink<Reply, Error = LineError> + Unpin,
+{
+    async fn next_item(&mut self) -> Option<Result<BytesMut, LineError>> {
+        if self.end.lock().unwrap().is_some() {
+            return None;
+        }
+
+        loop {
+            match self.socket.next().await {
+                Some(Err(LineError::ChunkingDone)) if self.last => {
+                    return self.finish(ChunksEnd::Last);
+                }
+                Some(Err(LineError::ChunkingDone)) => {
+                    if let Err(e) = self.next_chunk().await {
+                        return self.finish(e);
+                    }
+                }
+                Some(item) => return Some(item),
+                None => return self.finish(ChunksEnd::Failed(ServerError::EOF)),
+            }
+        }
+    }
+
+    /// Acknowledge the chunk just read and start the next one.
+    async fn next_chunk(&mut self) -> Result<(), ChunksEnd> {
+        let timeouts = &self.config.timeouts;
+
+        self.socket
+            .feed(Reply::ok())
+            .await
+            .map_err(|e| ChunksEnd::Failed(e.into()))?;
+        if self.socket.read_buffer().is_empty() {
+            self.socket
+                .flush()
+                .await
+                .map_err(|e| ChunksEnd::Failed(e.into()))?;
+        }
+
+        self.deadline.command(timeouts.data_block);
+        let line = match self.socket.next().await {
+            Some(line) => line,
+            None => return Err(ChunksEnd::Failed(ServerError::EOF)),
+        };
+        let (size, last) = match &line {
+            Ok(command) => match bdat_command(command) {
+                Ok((b"", bdat)) => bdat,
+                _ => return Err(ChunksEnd::Interrupted(line)),
+            },
+            Err(_) => return Err(ChunksEnd::Interrupted(line)),
+        };
+
+        self.deadline.data(
+            timeouts.data_block,
+            timeouts.data_block,
+            timeouts.min_data_rate,
+        );
+        self.size = self.size.saturating_add(size);
+        self.last = last;
+        if self
+            .config
+            .max_message_size
+            .is_some_and(|max| self.size > max)
+        {
+            let discarded = match read_body_bdat(self.socket, size) {
+                Ok(body) => drain(body).await,
+                Err(e) => Err(e),
+            };
+            return Err(match discarded {
+                Ok(()) => ChunksEnd::TooLarge { last },
+                Err(e) => ChunksEnd::Failed(e.into()),
+            });
+        }
+
+        self.socket
+            .codec_mut()
+            .chunking_mode(size)
+            .map_err(|e| ChunksEnd::Failed(e.into()))
+    }
+
+    /// Record the end of the body, with the error the handler sees
+    /// unless the message is complete.
+    fn finish(&mut self, end: ChunksEnd) -> Option<Result<BytesMut, LineError>> {
+        let item = match end {
+            ChunksEnd::Last => None,
+            ChunksEnd::TooLarge { .. } => Some(Err(LineError::MessageTooLarge)),
+            _ => Some(Err(LineError::DataAbort)),
+        };
+        *self.end.lock().unwrap() = Some(end);
+        item
+    }
+}
+
+/// Body of a unified BDAT message, from the chunk of the BDAT command
+/// just read to the one marked LAST, `size` bytes being announced so
+/// far.
+///
+/// Intermediate chunks are acknowledged and the next BDAT command is
+/// read as the stream is polled. The stream ends early with an error
+/// when another command comes instead or a chunk is too large,
+/// `end` tells how it ended.
+fn read_message_bdat<'a, S>(
+    socket: &'a mut Framed<S, LineCodec>,
+    size: u64,
+    last: bool,
+    config: &'a Config,
+    deadline: Deadline,
+    end: Arc<Mutex<Option<ChunksEnd>>>,
+) -> Result<impl Stream<Item = Result<BytesMut, LineError>> + Unpin + 'a, LineError>
+where
+    Framed<S, LineCodec>:
+        Stream<Item = Result<BytesMut, LineError>> + Sink<Reply, Error = LineError> + Unpin,
+{
+    socket.codec_mut().chunking_mode(size)?;
+    let reader = ChunkReader {
+        socket,
+        size,
+        last,
+        config,
+        deadline,
+        end,
+    };
+
+    Ok(Box::pin(stream::unfold(reader, |mut reader| async move {
+        let item = reader.next_item().await?;
+        Some((item, reader))
+    })))
 }
 
 fn read_body_bdat<'a, S>(
     socket: &'a mut Framed<S, LineCodec>,
     size: u64,
-) -> impl Stream<Item = Result<BytesMut, LineError>> + 'a
+) -> Result<impl Stream<Item = Result<BytesMut, LineError>> + 'a, LineError>
 where
     Framed<S, LineCodec>: Stream<Item = Result<BytesMut, LineError>> + Unpin,
 {
@@ -561,9 +2245,9 @@ where
     let abort = futures::stream::once(ready(Err(LineError::DataAbort)))
         .filter(move |_| ready(gen_abort.load(Ordering::SeqCst)));
 
-    socket.codec_mut().chunking_mode(size);
+    socket.codec_mut().chunking_mode(size)?;
 
-    socket
+    Ok(socket
         .take_while(move |chunk| {
             let more = match chunk {
                 Err(LineError::ChunkingDone) => {
@@ -575,5 +2259,5 @@ where
 
             ready(more)
         })
-        .chain(abort)
+        .chain(abort))
 }
@@ -0,0 +1,151 @@
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use rustyknife::types::DomainPart;
+
+use crate::Xclient;
+
+/// Session state maintained by the server and handed to every
+/// [`Handler`](crate::Handler) callback.
+///
+/// The server keeps it up to date, including across STARTTLS and
+/// overrides by PROXY and XCLIENT. `data` is the handler's own
+/// per-session data, see [`Handler::SessionData`](crate::Handler::SessionData).
+#[derive(Debug)]
+pub struct Session<D> {
+    socket_peer_addr: Option<SocketAddr>,
+    pub(crate) peer_addr: Option<SocketAddr>,
+    pub(crate) local_addr: Option<SocketAddr>,
+    pub(crate) helo: Option<DomainPart>,
+    pub(crate) tls: bool,
+    pub(crate) authenticated: Option<String>,
+    pub(crate) xclient: Option<Xclient>,
+    pub(crate) transactions: u64,
+    pub(crate) improper_pipelining: bool,
+    pub(crate) early_talker: bool,
+    pub(crate) syntax_errors: u64,
+    pub(crate) unknown_commands: u64,
+    pub(crate) rejected_recipients: u64,
+    pub(crate) error_replies: u64,
+    pub(crate) started: Instant,
+    data: D,
+}
+
+impl<D> Session<D> {
+    pub fn new(peer_addr: Option<SocketAddr>, local_addr: Option<SocketAddr>, data: D) -> Self {
+        Self {
+            socket_peer_addr: peer_addr,
+            peer_addr,
+            local_addr,
+            helo: None,
+            tls: false,
+            authenticated: None,
+            xclient: None,
+            transactions: 0,
+            improper_pipelining: false,
+            early_talker: false,
+            syntax_errors: 0,
+            unknown_commands: 0,
+            rejected_recipients: 0,
+            error_replies: 0,
+            started: Instant::now(),
+            data,
+        }
+    }
+
+    /// Client address, as reported by PROXY or XCLIENT if used.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// Address of the connected peer, as given to [`Session::new`].
+    /// Unlike `peer_addr` it is never overridden, so trust decisions
+    /// should use it.
+    pub fn socket_peer_addr(&self) -> Option<SocketAddr> {
+        self.socket_peer_addr
+    }
+
+    /// Address the client connected to.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    /// Domain from the last accepted HELO or EHLO.
+    pub fn helo(&self) -> Option<&DomainPart> {
+        self.helo.as_ref()
+    }
+
+    /// Whether the connection is protected by TLS.
+    pub fn tls(&self) -> bool {
+        self.tls
+    }
+
+    /// Identity from AUTH or the XCLIENT LOGIN attribute.
+    pub fn authenticated(&self) -> Option<&str> {
+        self.authenticated.as_deref()
+    }
+
+    /// Attributes accepted with XCLIENT, merged across commands.
+    pub fn xclient(&self) -> Option<&Xclient> {
+        self.xclient.as_ref()
+    }
+
+    /// Number of mail transactions started with an accepted MAIL.
+    pub fn transactions(&self) -> u64 {
+        self.transactions
+    }
+
+    /// Whether the client broke RFC 2920 pipelining rules.
+    pub fn improper_pipelining(&self) -> bool {
+        self.improper_pipelining
+    }
+
+    /// Whether the client talked before the banner, see
+    /// [`Config::greeting_delay`](crate::Config::greeting_delay).
+    pub fn early_talker(&self) -> bool {
+        self.early_talker
+    }
+
+    /// Number of malformed commands and over-long lines.
+    pub fn syntax_errors(&self) -> u64 {
+        self.syntax_errors
+    }
+
+    /// Number of unrecognized or unimplemented commands.
+    pub fn unknown_commands(&self) -> u64 {
+        self.unknown_commands
+    }
+
+    /// Number of RCPT commands answered with an error.
+    pub fn rejected_recipients(&self) -> u64 {
+        self.rejected_recipients
+    }
+
+    /// Number of 4xx and 5xx replies sent, see
+    /// [`Config::tarpit`](crate::Config::tarpit).
+    pub fn error_replies(&self) -> u64 {
+        self.error_replies
+    }
+
+    pub fn data(&self) -> &D {
+        &self.data
+    }
+
+    /// Mutable access for the owner of the session, between calls to
+    /// the server.
+    pub fn data_mut(&mut self) -> &mut D {
+        &mut self.data
+    }
+
+    pub fn into_data(self) -> D {
+        self.data
+    }
+
+    /// Forget what the client said before STARTTLS (RFC 3207
+    /// section 4.2).
+    pub(crate) fn tls_started(&mut self) {
+        self.tls = true;
+        self.helo = None;
+        self.authenticated = None;
+    }
+}
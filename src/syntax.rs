@@ -1,9 +1,14 @@
 use nom::branch::alt;
-use nom::combinator::map;
+use nom::bytes::complete::{tag, tag_no_case, take_while1, take_while_m_n};
+use nom::combinator::{map, map_opt, opt};
+use nom::multi::{many1, separated_nonempty_list};
+use nom::sequence::{delimited, pair, preceded, separated_pair, terminated};
 
 use rustyknife::rfc5321::{
-    bdat_command, command as base_command, starttls_command, Command as BaseCommand, UTF8Policy,
+    bdat_command, command as base_command, ehlo_command, starttls_command, Command as BaseCommand,
+    UTF8Policy,
 };
+use rustyknife::types::DomainPart;
 use rustyknife::xforward::{command as xforward_command, Param as XforwardParam};
 use rustyknife::NomResult;
 
@@ -18,8 +23,20 @@ pub enum Ext {
     STARTTLS,
     BDAT(u64, bool),
     XFORWARD(Vec<XforwardParam>),
+    /// Mechanism name in uppercase and the undecoded initial response.
+    AUTH(String, Option<String>),
+    LHLO(DomainPart),
+    XCLIENT(Vec<XclientParam>),
 }
 
+/// XCLIENT attribute name and value.
+///
+/// The name is normalized to lowercase and the value is xtext
+/// decoded. `[UNAVAILABLE]` and `[TEMPUNAVAIL]` are represented with a
+/// value of `None`.
+#[derive(Clone, Debug)]
+pub struct XclientParam(pub &'static str, pub Option<String>);
+
 pub fn command<P: UTF8Policy>(input: &[u8]) -> NomResult<'_, Command> {
     alt((
         map(base_command::<P>, Command::Base),
@@ -30,5 +47,156 @@ pub fn command<P: UTF8Policy>(input: &[u8]) -> NomResult<'_, Command> {
         map(xforward_command, |params| {
             Command::Ext(Ext::XFORWARD(params))
         }),
+        map(auth_command, |(mechanism, initial)| {
+            Command::Ext(Ext::AUTH(mechanism, initial))
+        }),
+        map(lhlo_command::<P>, |domain| Command::Ext(Ext::LHLO(domain))),
+        map(xclient_command, |params| Command::Ext(Ext::XCLIENT(params))),
+    ))(input)
+}
+
+/// Parse an LMTP LHLO command from RFC 2033.
+pub fn lhlo_command<P: UTF8Policy>(input: &[u8]) -> NomResult<'_, DomainPart> {
+    let (rem, _) = tag_no_case("LHLO")(input)?;
+
+    // LHLO has the exact same syntax as EHLO.
+    let mut ehlo = b"EHLO".to_vec();
+    ehlo.extend_from_slice(rem);
+
+    match ehlo_command::<P>(&ehlo) {
+        Ok((ehlo_rem, domain)) => Ok((&rem[rem.len() - ehlo_rem.len()..], domain)),
+        Err(_) => Err(nom::Err::Error(())),
+    }
+}
+
+fn is_mechanism_char(c: u8) -> bool {
+    c.is_ascii_uppercase() || c.is_ascii_digit() || c == b'-' || c == b'_'
+}
+
+fn is_base64_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'+' || c == b'/' || c == b'='
+}
+
+fn sasl_mechanism(input: &[u8]) -> NomResult<'_, String> {
+    map(
+        take_while_m_n(1, 20, |c: u8| is_mechanism_char(c.to_ascii_uppercase())),
+        |m: &[u8]| String::from_utf8_lossy(m).to_ascii_uppercase(),
+    )(input)
+}
+
+/// Parse an AUTH command from RFC 4954.
+pub fn auth_command(input: &[u8]) -> NomResult<'_, (String, Option<String>)> {
+    terminated(
+        pair(
+            preceded(tag_no_case("AUTH "), sasl_mechanism),
+            opt(preceded(
+                tag(" "),
+                map(take_while1(is_base64_char), |r: &[u8]| {
+                    String::from_utf8_lossy(r).into_owned()
+                }),
+            )),
+        ),
+        tag("\r\n"),
+    )(input)
+}
+
+fn xclient_name(input: &[u8]) -> NomResult<'_, &'static str> {
+    alt((
+        map(tag_no_case("NAME"), |_| "name"),
+        map(tag_no_case("ADDR"), |_| "addr"),
+        map(tag_no_case("PORT"), |_| "port"),
+        map(tag_no_case("PROTO"), |_| "proto"),
+        map(tag_no_case("HELO"), |_| "helo"),
+        map(tag_no_case("LOGIN"), |_| "login"),
+        map(tag_no_case("DESTADDR"), |_| "destaddr"),
+        map(tag_no_case("DESTPORT"), |_| "destport"),
+    ))(input)
+}
+
+fn xclient_value(input: &[u8]) -> NomResult<'_, Option<String>> {
+    alt((
+        map(tag_no_case("[UNAVAILABLE]"), |_| None),
+        map(tag_no_case("[TEMPUNAVAIL]"), |_| None),
+        map_opt(
+            take_while1(|c: u8| (33..=126).contains(&c) && c != b'='),
+            |v: &[u8]| xtext_decode(std::str::from_utf8(v).ok()?).map(Some),
+        ),
     ))(input)
 }
+
+/// Parse a Postfix XCLIENT command.
+///
+/// See <http://www.postfix.org/XCLIENT_README.html>.
+pub fn xclient_command(input: &[u8]) -> NomResult<'_, Vec<XclientParam>> {
+    delimited(
+        tag_no_case("XCLIENT "),
+        separated_nonempty_list(
+            many1(tag(" ")),
+            map(
+                separated_pair(xclient_name, tag("="), xclient_value),
+                |(name, value)| XclientParam(name, value),
+            ),
+        ),
+        tag("\r\n"),
+    )(input)
+}
+
+/// Decode an xtext string from RFC 3461.
+///
+/// Only ASCII results are accepted, as xtext is meant to carry.
+pub fn xtext_decode(input: &str) -> Option<String> {
+    let mut decoded = String::with_capacity(input.len());
+    let mut bytes = input.bytes();
+
+    while let Some(c) = bytes.next() {
+        match c {
+            b'+' => {
+                let c = (hex_digit(bytes.next()?)? << 4) | hex_digit(bytes.next()?)?;
+                if !c.is_ascii() {
+                    return None;
+                }
+                decoded.push(char::from(c));
+            }
+            b'=' => return None,
+            33..=126 => decoded.push(char::from(c)),
+            _ => return None,
+        }
+    }
+
+    Some(decoded)
+}
+
+/// Value of an xtext hex digit, which must be uppercase.
+fn hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xtext() {
+        assert_eq!(xtext_decode("").as_deref(), Some(""));
+        assert_eq!(xtext_decode("plain").as_deref(), Some("plain"));
+        assert_eq!(xtext_decode("a+2Bb+3Dc").as_deref(), Some("a+b=c"));
+        assert_eq!(xtext_decode("+2B+3D+7E").as_deref(), Some("+=~"));
+        assert_eq!(xtext_decode("[a]").as_deref(), Some("[a]"));
+
+        // Truncated, invalid or lowercase hex, excluded characters.
+        assert_eq!(xtext_decode("a+"), None);
+        assert_eq!(xtext_decode("a+2"), None);
+        assert_eq!(xtext_decode("+2b"), None);
+        assert_eq!(xtext_decode("+2B+3d"), None);
+        assert_eq!(xtext_decode("a+zz"), None);
+        assert_eq!(xtext_decode("a=b"), None);
+        assert_eq!(xtext_decode("a b"), None);
+        assert_eq!(xtext_decode("a NAME=b"), None);
+        assert_eq!(xtext_decode("caf\u{e9}"), None);
+        assert_eq!(xtext_decode("caf+C3+A9"), None);
+    }
+}
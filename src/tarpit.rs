@@ -0,0 +1,86 @@
+use std::convert::TryFrom;
+use std::time::Duration;
+
+/// Progressive delay of error replies against dictionary attacks,
+/// similar to Postfix's `smtpd_error_sleep_time`.
+///
+/// Each 4xx or 5xx reply past the first `free_errors` of a session is
+/// delayed by `initial`, plus `increment` for every error before it,
+/// up to `max`. A handler may override the delay of a reply with
+/// [`Reply::with_delay`](crate::Reply::with_delay).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tarpit {
+    pub free_errors: u64,
+    pub initial: Duration,
+    pub increment: Duration,
+    pub max: Duration,
+}
+
+impl Tarpit {
+    /// Delay of the error reply numbered `errors`, starting at 1.
+    pub fn delay(&self, errors: u64) -> Duration {
+        if errors <= self.free_errors {
+            return Duration::from_secs(0);
+        }
+
+        let steps = u32::try_from(errors - self.free_errors - 1).unwrap_or(u32::MAX);
+        let delay = self
+            .increment
+            .checked_mul(steps)
+            .and_then(|extra| self.initial.checked_add(extra))
+            .unwrap_or(self.max);
+
+        delay.min(self.max)
+    }
+}
+
+impl Default for Tarpit {
+    fn default() -> Self {
+        Tarpit {
+            free_errors: 3,
+            initial: Duration::from_secs(1),
+            increment: Duration::from_secs(1),
+            max: Duration::from_secs(30),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn progressive_delay() {
+        let tarpit = Tarpit {
+            free_errors: 2,
+            initial: ms(100),
+            increment: ms(50),
+            max: ms(250),
+        };
+
+        let delays: Vec<_> = (1..=7).map(|errors| tarpit.delay(errors)).collect();
+        assert_eq!(
+            delays,
+            [ms(0), ms(0), ms(100), ms(150), ms(200), ms(250), ms(250)]
+        );
+    }
+
+    #[test]
+    fn no_overflow() {
+        let tarpit = Tarpit {
+            free_errors: 0,
+            initial: Duration::MAX,
+            increment: Duration::MAX,
+            max: ms(500),
+        };
+
+        assert_eq!(tarpit.delay(1), ms(500));
+        assert_eq!(tarpit.delay(2), ms(500));
+        assert_eq!(tarpit.delay(u64::MAX), ms(500));
+        assert_eq!(Tarpit::default().delay(u64::MAX), Duration::from_secs(30));
+    }
+}
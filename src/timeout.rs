@@ -0,0 +1,292 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bytes::BytesMut;
+use futures_util::future::{select, Either};
+use futures_util::pin_mut;
+use futures_util::stream::{self, Stream, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::time::{delay_until, Delay, Instant};
+
+use crate::{LineError, ServerError};
+
+/// Session timeouts, defaults follow RFC 5321 4.5.3.2.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Timeouts {
+    /// Waiting for the first command after the banner.
+    pub greeting: Duration,
+    /// Waiting for a command outside of a mail transaction, such as
+    /// MAIL.
+    pub mail: Duration,
+    /// Waiting for a command within a mail transaction, such as RCPT.
+    pub rcpt: Duration,
+    /// Waiting for the message after the 354 reply to DATA.
+    pub data_initiation: Duration,
+    /// Waiting for each read of message data, with DATA or BDAT.
+    pub data_block: Duration,
+    /// Handler processing once the message data was received.
+    pub data_termination: Duration,
+    /// Completing the TLS handshake, for STARTTLS and SMTPS.
+    pub tls_handshake: Duration,
+    /// Limit on the whole session from [`Session::new`](crate::Session::new),
+    /// the PROXY header, greeting delay and STARTTLS included.
+    pub session: Option<Duration>,
+    /// Minimum average rate in bytes per second while receiving
+    /// message data, after an allowance of `data_block`.
+    pub min_data_rate: Option<u64>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            greeting: Duration::from_secs(5 * 60),
+            mail: Duration::from_secs(5 * 60),
+            rcpt: Duration::from_secs(5 * 60),
+            data_initiation: Duration::from_secs(2 * 60),
+            data_block: Duration::from_secs(3 * 60),
+            data_termination: Duration::from_secs(10 * 60),
+            tls_handshake: Duration::from_secs(5 * 60),
+            session: None,
+            min_data_rate: None,
+        }
+    }
+}
+
+/// Deadlines of the current phase, shared by the socket reader and
+/// the handler calls.
+#[derive(Clone)]
+pub(crate) struct Deadline(Arc<Mutex<DeadlineState>>);
+
+struct DeadlineState {
+    session: Option<Instant>,
+    phase: Option<Instant>,
+    /// Each read pushes `phase` back by this much.
+    idle: Option<Duration>,
+    rate: Option<Rate>,
+    expired: bool,
+}
+
+struct Rate {
+    start: Instant,
+    per_second: u64,
+    bytes: u64,
+}
+
+impl DeadlineState {
+    fn next(&self) -> Option<Instant> {
+        let rate = self.rate.as_ref().and_then(|rate| {
+            rate.start.checked_add(Duration::from_secs_f64(
+                rate.bytes as f64 / rate.per_second as f64,
+            ))
+        });
+
+        [self.session, self.phase, rate]
+            .iter()
+            .flatten()
+            .min()
+            .copied()
+    }
+}
+
+impl Deadline {
+    pub(crate) fn new(session: Option<std::time::Instant>) -> Self {
+        Self(Arc::new(Mutex::new(DeadlineState {
+            session: session.map(Instant::from_std),
+            phase: None,
+            idle: None,
+            rate: None,
+            expired: false,
+        })))
+    }
+
+    /// Allow `timeout` for the next command, or for processing.
+    ///
+    /// Durations too long to be represented mean no deadline, here
+    /// and in [`Deadline::data`].
+    pub(crate) fn command(&self, timeout: Duration) {
+        let mut state = self.0.lock().unwrap();
+        state.phase = Instant::now().checked_add(timeout);
+        state.idle = None;
+        state.rate = None;
+    }
+
+    /// Allow `initiation` for the first read of message data, then
+    /// `block` after each read.
+    pub(crate) fn data(&self, initiation: Duration, block: Duration, min_rate: Option<u64>) {
+        let now = Instant::now();
+        let mut state = self.0.lock().unwrap();
+        state.phase = now.checked_add(initiation);
+        state.idle = Some(block);
+        state.rate = min_rate.and_then(|per_second| {
+            Some(Rate {
+                start: now.checked_add(block)?,
+                per_second: per_second.max(1),
+                bytes: 0,
+            })
+        });
+    }
+
+    /// Switch to `timeout` for processing once `body` ends.
+    pub(crate) fn then_processing<'a, S>(
+        &self,
+        body: S,
+        timeout: Duration,
+    ) -> impl Stream<Item = Result<BytesMut, LineError>> + 'a
+    where
+        S: Stream<Item = Result<BytesMut, LineError>> + 'a,
+    {
+        let deadline = self.clone();
+
+        body.chain(stream::poll_fn(move |_| {
+            deadline.command(timeout);
+            Poll::Ready(None)
+        }))
+    }
+
+    pub(crate) fn expired(&self) -> bool {
+        self.0.lock().unwrap().expired
+    }
+
+    /// Run `future` until it completes or the deadline passes.
+    pub(crate) async fn bounded<F: Future>(&self, future: F) -> Result<F::Output, ServerError> {
+        let expiry = Expiry {
+            deadline: self.clone(),
+            delay: None,
+        };
+        pin_mut!(future);
+
+        match select(future, expiry).await {
+            Either::Left((output, _)) => Ok(output),
+            Either::Right(_) => Err(ServerError::Timeout),
+        }
+    }
+
+    fn read(&self, bytes: usize) {
+        let mut state = self.0.lock().unwrap();
+        if let Some(idle) = state.idle {
+            state.phase = Instant::now().checked_add(idle);
+        }
+        if let Some(rate) = &mut state.rate {
+            rate.bytes += bytes as u64;
+        }
+    }
+
+    /// Ready once the earliest deadline passed, arming `delay` to be
+    /// woken up for it otherwise.
+    fn poll_expired(&self, delay: &mut Option<Delay>, cx: &mut Context<'_>) -> Poll<()> {
+        let next = {
+            let mut state = self.0.lock().unwrap();
+            let next = match (state.expired, state.next()) {
+                (true, _) => return Poll::Ready(()),
+                (false, None) => return Poll::Pending,
+                (false, Some(next)) => next,
+            };
+            if Instant::now() >= next {
+                state.expired = true;
+                return Poll::Ready(());
+            }
+            next
+        };
+
+        let delay = match delay {
+            Some(delay) => {
+                if delay.deadline() != next {
+                    delay.reset(next);
+                }
+                delay
+            }
+            None => delay.get_or_insert_with(|| delay_until(next)),
+        };
+
+        match Pin::new(delay).poll(cx) {
+            Poll::Ready(()) => {
+                self.0.lock().unwrap().expired = true;
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+struct Expiry {
+    deadline: Deadline,
+    delay: Option<Delay>,
+}
+
+impl Future for Expiry {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = &mut *self;
+        this.deadline.poll_expired(&mut this.delay, cx)
+    }
+}
+
+/// Socket whose reads and writes fail with `TimedOut` once the
+/// deadline passed.
+pub(crate) struct Timed<S> {
+    inner: S,
+    deadline: Deadline,
+    delay: Option<Delay>,
+}
+
+impl<S> Timed<S> {
+    pub(crate) fn new(inner: S, deadline: Deadline) -> Self {
+        Self {
+            inner,
+            deadline,
+            delay: None,
+        }
+    }
+
+    /// Replace `res` with `TimedOut` once the deadline passed.
+    fn check<T>(&mut self, res: Poll<io::Result<T>>, cx: &mut Context<'_>) -> Poll<io::Result<T>> {
+        match (res, self.deadline.poll_expired(&mut self.delay, cx)) {
+            (Poll::Ready(Err(e)), _) => Poll::Ready(Err(e)),
+            (_, Poll::Ready(())) => Poll::Ready(Err(io::ErrorKind::TimedOut.into())),
+            (res, Poll::Pending) => res,
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Timed<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let res = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(read)) = res {
+            this.deadline.read(read);
+        }
+
+        this.check(res, cx)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Timed<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let res = Pin::new(&mut this.inner).poll_write(cx, buf);
+        this.check(res, cx)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        let res = Pin::new(&mut this.inner).poll_flush(cx);
+        this.check(res, cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
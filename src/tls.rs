@@ -0,0 +1,111 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use async_trait::async_trait;
+use bytes::{Buf, BytesMut};
+use tokio::prelude::*;
+use tokio_rustls::rustls::{ServerConfig, ServerSession};
+use tokio_rustls::server::TlsStream;
+
+/// TLS implementation used to secure a session.
+#[async_trait]
+pub trait TlsAcceptor<IO>: Sync
+where
+    IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    /// Configuration returned by [`Handler::tls_request`](crate::Handler::tls_request).
+    type Config: Send;
+    /// Session handed to [`Handler::tls_started`](crate::Handler::tls_started).
+    type Session;
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send;
+
+    /// Perform the server side of the handshake on `io`.
+    async fn accept(&self, config: Self::Config, io: IO) -> io::Result<Self::Stream>;
+
+    fn session(stream: &Self::Stream) -> &Self::Session;
+}
+
+/// [`TlsAcceptor`] for rustls through tokio-rustls.
+pub struct RustlsAcceptor;
+
+#[async_trait]
+impl<IO> TlsAcceptor<IO> for RustlsAcceptor
+where
+    IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Config = Arc<ServerConfig>;
+    type Session = ServerSession;
+    type Stream = TlsStream<IO>;
+
+    async fn accept(&self, config: Self::Config, io: IO) -> io::Result<Self::Stream> {
+        tokio_rustls::TlsAcceptor::from(config).accept(io).await
+    }
+
+    fn session(stream: &Self::Stream) -> &Self::Session {
+        stream.get_ref().1
+    }
+}
+
+/// Socket that first replays bytes already read from it.
+///
+/// Used to hand over the bytes read while looking for a PROXY
+/// header.
+pub struct Rewind<S> {
+    prefix: BytesMut,
+    inner: S,
+}
+
+impl<S> Rewind<S> {
+    pub fn new(inner: S, prefix: BytesMut) -> Self {
+        Self { prefix, inner }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Rewind<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.prefix.is_empty() {
+            return Pin::new(&mut self.inner).poll_read(cx, buf);
+        }
+
+        let len = buf.len().min(self.prefix.len());
+        buf[..len].copy_from_slice(&self.prefix[..len]);
+        self.prefix.advance(len);
+        Poll::Ready(Ok(len))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Rewind<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
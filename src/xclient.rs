@@ -0,0 +1,89 @@
+use std::net::IpAddr;
+
+use crate::{EnhancedCode, Reply, XclientParam};
+
+/// Attributes advertised with XCLIENT.
+pub const XCLIENT_ATTRIBUTES: &str = "NAME ADDR PORT PROTO HELO LOGIN DESTADDR DESTPORT";
+
+/// Client attributes overridden by a trusted proxy with XCLIENT.
+///
+/// See <http://www.postfix.org/XCLIENT_README.html>. Attributes are
+/// `None` when not sent or sent as `[UNAVAILABLE]` or `[TEMPUNAVAIL]`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Xclient {
+    pub name: Option<String>,
+    pub addr: Option<IpAddr>,
+    pub port: Option<u16>,
+    /// `"SMTP"` or `"ESMTP"`.
+    pub proto: Option<String>,
+    pub helo: Option<String>,
+    /// SASL login name of the original client.
+    pub login: Option<String>,
+    pub destaddr: Option<IpAddr>,
+    pub destport: Option<u16>,
+}
+
+impl Xclient {
+    /// Merge the attributes of one XCLIENT command.
+    ///
+    /// Attributes that are not sent keep their value. Nothing is
+    /// changed if any attribute is invalid.
+    pub fn update(&mut self, params: Vec<XclientParam>) -> Result<(), Reply> {
+        let mut updated = self.clone();
+
+        for XclientParam(name, value) in params {
+            let value = value.as_deref();
+            match name {
+                "name" => updated.name = value.map(Into::into),
+                "addr" => updated.addr = parse_addr(name, value)?,
+                "port" => updated.port = parse_port(name, value)?,
+                "proto" => {
+                    updated.proto = match value.map(str::to_ascii_uppercase) {
+                        Some(proto) if proto == "SMTP" || proto == "ESMTP" => Some(proto),
+                        Some(_) => return Err(invalid(name)),
+                        None => None,
+                    }
+                }
+                "helo" => updated.helo = value.map(Into::into),
+                "login" => updated.login = value.map(Into::into),
+                "destaddr" => updated.destaddr = parse_addr(name, value)?,
+                "destport" => updated.destport = parse_port(name, value)?,
+                _ => return Err(invalid(name)),
+            }
+        }
+
+        *self = updated;
+        Ok(())
+    }
+}
+
+fn parse_addr(name: &str, value: Option<&str>) -> Result<Option<IpAddr>, Reply> {
+    let value = match value {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    // IPv6 addresses carry an "IPV6:" prefix.
+    let addr = match value.get(..5) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("IPV6:") => {
+            value[5..].parse().ok().filter(IpAddr::is_ipv6)
+        }
+        _ => value.parse().ok().filter(IpAddr::is_ipv4),
+    };
+
+    addr.map(Some).ok_or_else(|| invalid(name))
+}
+
+fn parse_port(name: &str, value: Option<&str>) -> Result<Option<u16>, Reply> {
+    match value {
+        Some(port) => port.parse().map(Some).map_err(|_| invalid(name)),
+        None => Ok(None),
+    }
+}
+
+fn invalid(name: &str) -> Reply {
+    Reply::new(
+        501,
+        Some(EnhancedCode(5, 5, 4)),
+        format!("Bad XCLIENT attribute {}", name.to_ascii_uppercase()),
+    )
+}
@@ -0,0 +1,70 @@
+use rustyknife::xforward::Param as XforwardParam;
+
+use crate::{EnhancedCode, Reply};
+
+/// Attributes advertised with XFORWARD.
+pub const XFORWARD_ATTRIBUTES: &str = "NAME ADDR PORT PROTO HELO IDENT SOURCE";
+
+/// Client attributes forwarded by a Postfix content filter.
+///
+/// See <http://www.postfix.org/XFORWARD_README.html>. Attributes are
+/// `None` when not sent or sent as `[UNAVAILABLE]`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Xforward {
+    pub name: Option<String>,
+    pub addr: Option<String>,
+    pub port: Option<u16>,
+    pub proto: Option<String>,
+    pub helo: Option<String>,
+    pub ident: Option<String>,
+    pub source: Option<String>,
+}
+
+impl Xforward {
+    /// Merge the attributes of one XFORWARD command.
+    ///
+    /// Nothing is changed if any attribute is invalid.
+    pub fn update(&mut self, params: Vec<XforwardParam>) -> Result<(), Reply> {
+        let mut updated = self.clone();
+
+        for XforwardParam(name, value) in params {
+            match name {
+                "name" => updated.name = value,
+                "addr" => updated.addr = value,
+                "port" => {
+                    updated.port = match value {
+                        Some(port) => Some(port.parse().map_err(|_| invalid(name))?),
+                        None => None,
+                    }
+                }
+                "proto" => updated.proto = value,
+                "helo" => updated.helo = value,
+                "ident" => updated.ident = value,
+                "source" => {
+                    updated.source = match value {
+                        Some(source)
+                            if source.eq_ignore_ascii_case("LOCAL")
+                                || source.eq_ignore_ascii_case("REMOTE") =>
+                        {
+                            Some(source.to_ascii_uppercase())
+                        }
+                        Some(_) => return Err(invalid(name)),
+                        None => None,
+                    }
+                }
+                _ => return Err(invalid(name)),
+            }
+        }
+
+        *self = updated;
+        Ok(())
+    }
+}
+
+fn invalid(name: &str) -> Reply {
+    Reply::new(
+        501,
+        Some(EnhancedCode(5, 5, 4)),
+        format!("Bad XFORWARD attribute {}", name.to_ascii_uppercase()),
+    )
+}
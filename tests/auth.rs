@@ -0,0 +1,98 @@
+mod common;
+
+use common::Test;
+use smtpbis::{Config, Credentials};
+
+fn auth_config() -> Config {
+    Config {
+        hostname: "mx.example.org".into(),
+        auth_mechanisms: vec!["PLAIN".into(), "LOGIN".into(), "CRAM-MD5".into()],
+        ..Config::default()
+    }
+}
+
+#[test]
+fn plain_initial_response() {
+    let mut test = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "AUTH PLAIN AHVzZXIAcGVuY2ls\r\n",
+        "QUIT\r\n",
+    ]);
+    test.config = auth_config();
+    let out = test.run();
+
+    assert!(out.replied("250-AUTH PLAIN LOGIN CRAM-MD5"));
+    assert_eq!(out.codes(), [220, 250, 235, 221]);
+    assert_eq!(out.session.authenticated(), Some("user"));
+}
+
+#[test]
+fn login_challenges() {
+    let mut test = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "AUTH LOGIN\r\n",
+        "dXNlcg==\r\n",
+        "d3Jvbmc=\r\n",
+        "AUTH LOGIN dXNlcg==\r\n",
+        "cGVuY2ls\r\n",
+        "AUTH PLAIN AHVzZXIAcGVuY2ls\r\n",
+    ]);
+    test.config = auth_config();
+    let out = test.run();
+
+    assert_eq!(out.codes(), [220, 250, 334, 334, 535, 334, 235, 503]);
+    assert_eq!(out.session.authenticated(), Some("user"));
+}
+
+#[test]
+fn cram_md5_uses_hostname() {
+    let mut test = Test::new(&["EHLO client.example.org\r\n", "AUTH CRAM-MD5\r\n", "*\r\n"]);
+    test.config = auth_config();
+    let out = test.run();
+
+    assert_eq!(out.codes(), [220, 250, 334, 501]);
+    assert!(out.replied("220 mx.example.org ESMTP"));
+    let challenge = out.last_lines()[2][4..].to_string();
+    let challenge = String::from_utf8(base64::decode(&challenge).unwrap()).unwrap();
+    assert!(challenge.ends_with("@mx.example.org>"), "{}", challenge);
+}
+
+#[test]
+fn rejected_exchanges() {
+    let mut test = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "AUTH SCRAM-SHA-1\r\n",
+        "AUTH PLAIN abc\r\n",
+        "AUTH PLAIN AHVzZXIAd3Jvbmc=\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "AUTH PLAIN AHVzZXIAcGVuY2ls\r\n",
+    ]);
+    test.config = auth_config();
+    let out = test.run();
+
+    assert_eq!(out.codes(), [220, 250, 504, 501, 535, 250, 503]);
+    assert_eq!(out.session.authenticated(), None);
+}
+
+#[test]
+fn not_advertised_without_mechanisms() {
+    let out = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "AUTH PLAIN AHVzZXIAcGVuY2ls\r\n",
+    ])
+    .run();
+
+    assert!(!out.replied("AUTH"));
+    assert_eq!(out.codes(), [220, 250, 502]);
+}
+
+#[test]
+fn verified_credentials_have_no_password() {
+    let credentials = Credentials::Verified {
+        authzid: None,
+        authcid: "user".into(),
+    };
+
+    assert!(!credentials.verify_password("pencil"));
+    assert!(!credentials.verify_password(""));
+}
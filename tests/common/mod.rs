@@ -0,0 +1,546 @@
+//! Session test harness: a scripted client socket and a recording
+//! handler.
+#![allow(dead_code)]
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::BytesMut;
+use futures::channel::oneshot;
+use futures::{FutureExt, Stream, StreamExt, TryFutureExt};
+use tokio::prelude::*;
+use tokio::time::{delay_for, Delay};
+
+use rustyknife::rfc5321::{ForwardPath, ReversePath};
+use rustyknife::types::{Domain, DomainPart};
+
+use smtpbis::{
+    smtp_session, smtps_server, BareNewline, Config, Credentials, EhloKeywords, Envelope, Handler,
+    LineError, MailParams, PipeliningViolation, ProxyHeader, RcptParams, Reply, Response,
+    ServerError, Session, TlsAcceptor, TransactionOutcome, Xclient, Xforward,
+};
+
+/// Client side of a session. Each segment is returned by a separate
+/// read, then the socket reads EOF or blocks forever.
+pub struct MockSocket {
+    input: VecDeque<Vec<u8>>,
+    hang: bool,
+    fail_shutdown: bool,
+    /// Writes block for this long from the first one.
+    stall_writes: Option<Duration>,
+    stall: Option<Delay>,
+    output: Arc<Mutex<Vec<u8>>>,
+    writes: Arc<AtomicUsize>,
+}
+
+impl AsyncRead for MockSocket {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.input.pop_front() {
+            Some(mut segment) => {
+                let read = segment.len().min(buf.len());
+                buf[..read].copy_from_slice(&segment[..read]);
+                if read < segment.len() {
+                    self.input.push_front(segment.split_off(read));
+                }
+                Poll::Ready(Ok(read))
+            }
+            // Woken by the server's timers only.
+            None if self.hang => Poll::Pending,
+            None => Poll::Ready(Ok(0)),
+        }
+    }
+}
+
+impl AsyncWrite for MockSocket {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if let Some(stall) = self.stall_writes {
+            let delay = self.stall.get_or_insert_with(|| delay_for(stall));
+            if Pin::new(delay).poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+        self.writes.fetch_add(1, Ordering::SeqCst);
+        self.output.lock().unwrap().extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.fail_shutdown {
+            Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()))
+        } else {
+            Poll::Ready(Ok(()))
+        }
+    }
+}
+
+/// "TLS" that leaves the stream as it is.
+pub struct PlainAcceptor {
+    /// Never complete the handshake, like a stalled client.
+    pub hang: bool,
+}
+
+#[async_trait]
+impl<IO> TlsAcceptor<IO> for PlainAcceptor
+where
+    IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Config = ();
+    type Session = ();
+    type Stream = IO;
+
+    async fn accept(&self, _config: (), io: IO) -> io::Result<IO> {
+        if self.hang {
+            futures::future::pending::<()>().await;
+        }
+        Ok(io)
+    }
+
+    fn session(_stream: &IO) -> &() {
+        &()
+    }
+}
+
+/// Handler recording what the server reports.
+///
+/// Recipients in domains starting with `z` are rejected. AUTH
+/// accepts `user` with password `pencil`. Peers connecting from
+/// loopback are trusted.
+#[derive(Default)]
+pub struct TestHandler {
+    /// Allow PROXY, XCLIENT and XFORWARD from any peer, and offer
+    /// STARTTLS.
+    pub trusted: bool,
+    /// Keep reading message bodies after an error.
+    pub read_past_errors: bool,
+    /// Response to rejected recipients instead of 550.
+    pub rcpt_rejection: Option<Response>,
+    /// Response to every message instead of 250.
+    pub message_response: Option<Response>,
+    /// Response to XFORWARD instead of accepting it.
+    pub xforward_response: Option<Response>,
+    /// Response to XCLIENT instead of accepting it.
+    pub xclient_response: Option<Response>,
+    /// Reply to clients talking before the banner.
+    pub early_talker_reply: Option<Reply>,
+    /// EHLO keywords left out of the reply.
+    pub hidden_keywords: Vec<&'static str>,
+    pub events: Vec<String>,
+    /// Envelope of each ended transaction.
+    pub envelopes: Vec<Envelope>,
+    /// XCLIENT attributes in the session at the last MAIL.
+    pub mail_xclient: Option<Xclient>,
+    /// Items of each message body, BDAT chunks collected together.
+    pub messages: Vec<Vec<Vec<u8>>>,
+    chunks: Vec<Vec<u8>>,
+}
+
+impl TestHandler {
+    pub fn trusted() -> Self {
+        Self {
+            trusted: true,
+            ..Self::default()
+        }
+    }
+
+    /// Body of message `index`.
+    pub fn message(&self, index: usize) -> Vec<u8> {
+        self.messages[index].concat()
+    }
+
+    pub fn has_event(&self, event: &str) -> bool {
+        self.events.iter().any(|e| e == event)
+    }
+
+    async fn read_body<S>(&mut self, stream: &mut S) -> Result<Vec<Vec<u8>>, Reply>
+    where
+        S: Stream<Item = Result<BytesMut, LineError>> + Unpin + Send,
+    {
+        let mut items = Vec::new();
+        let mut error = None;
+
+        while let Some(item) = stream.next().await {
+            match item {
+                Ok(item) => items.push(item.to_vec()),
+                Err(e) => {
+                    self.events.push(format!("body error {:?}", e));
+                    error = Some(Reply::new(451, None, format!("{:?}", e)));
+                    if !self.read_past_errors {
+                        break;
+                    }
+                }
+            }
+        }
+
+        match error {
+            Some(reply) => Err(reply),
+            None => Ok(items),
+        }
+    }
+
+    fn message_response(&self) -> Option<Response> {
+        self.message_response.clone()
+    }
+}
+
+#[async_trait]
+impl Handler for TestHandler {
+    type TlsConfig = ();
+    type TlsSession = ();
+    type SessionData = ();
+
+    async fn tls_request(&mut self, _session: &Session<()>) -> Option<()> {
+        if self.trusted {
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    async fn tls_started(&mut self, _session: &Session<()>, _tls_session: &()) {
+        self.events.push("tls".into());
+    }
+
+    async fn proxy_allowed(&mut self, session: &Session<()>) -> bool {
+        self.trusted || loopback(session)
+    }
+
+    async fn proxy(&mut self, _session: &Session<()>, header: &ProxyHeader) {
+        self.events.push(format!("proxy {:?}", header.source));
+    }
+
+    async fn early_talker(&mut self, _session: &Session<()>, data: &[u8]) -> Option<Reply> {
+        self.events
+            .push(format!("early talker {}", String::from_utf8_lossy(data)));
+        self.early_talker_reply.clone()
+    }
+
+    async fn ehlo(
+        &mut self,
+        _session: &Session<()>,
+        _domain: DomainPart,
+        mut initial_keywords: EhloKeywords,
+    ) -> Result<(String, EhloKeywords), Response> {
+        for keyword in &self.hidden_keywords {
+            initial_keywords.remove(*keyword);
+        }
+        Ok(("localhost greets you".into(), initial_keywords))
+    }
+
+    async fn helo(&mut self, _session: &Session<()>, _domain: Domain) -> Option<Response> {
+        None
+    }
+
+    async fn rset(&mut self, _session: &Session<()>) {}
+
+    async fn mail(
+        &mut self,
+        session: &Session<()>,
+        _path: ReversePath,
+        params: MailParams,
+    ) -> Option<Response> {
+        self.events.push(format!("mail {:?}", params));
+        self.mail_xclient = session.xclient().cloned();
+        None
+    }
+
+    async fn rcpt(
+        &mut self,
+        _session: &Session<()>,
+        path: ForwardPath,
+        _params: RcptParams,
+    ) -> Option<Response> {
+        let rejected = match &path {
+            ForwardPath::Path(path) => path.0.domain_part().to_string().starts_with('z'),
+            _ => false,
+        };
+        if !rejected {
+            return None;
+        }
+
+        Some(
+            self.rcpt_rejection
+                .clone()
+                .unwrap_or_else(|| Reply::new(550, None, "No such user").into()),
+        )
+    }
+
+    async fn data<S>(
+        &mut self,
+        _session: &Session<()>,
+        stream: &mut S,
+        _envelope: &Envelope,
+    ) -> Result<Option<Response>, ServerError>
+    where
+        S: Stream<Item = Result<BytesMut, LineError>> + Unpin + Send,
+    {
+        match self.read_body(stream).await {
+            Ok(items) => {
+                self.messages.push(items);
+                Ok(self.message_response())
+            }
+            Err(reply) => Ok(Some(reply.into())),
+        }
+    }
+
+    async fn bdat<S>(
+        &mut self,
+        _session: &Session<()>,
+        stream: &mut S,
+        _size: u64,
+        last: bool,
+        _envelope: &Envelope,
+    ) -> Result<Option<Response>, ServerError>
+    where
+        S: Stream<Item = Result<BytesMut, LineError>> + Unpin + Send,
+    {
+        match self.read_body(stream).await {
+            Ok(items) => {
+                self.chunks.push(items.concat());
+                if last {
+                    self.messages.push(std::mem::take(&mut self.chunks));
+                    return Ok(self.message_response());
+                }
+                Ok(None)
+            }
+            Err(reply) => Ok(Some(reply.into())),
+        }
+    }
+
+    async fn bare_newline(&mut self, _session: &Session<()>, bare: &BareNewline) {
+        self.events.push(format!("bare newline {:?}", bare));
+    }
+
+    async fn pipelining_violation(
+        &mut self,
+        _session: &Session<()>,
+        violation: &PipeliningViolation,
+    ) {
+        self.events.push(format!("pipelining {:?}", violation));
+    }
+
+    async fn transaction_end(
+        &mut self,
+        _session: &Session<()>,
+        envelope: &Envelope,
+        outcome: TransactionOutcome,
+    ) {
+        self.events.push(format!(
+            "transaction {:?} {}",
+            outcome,
+            envelope.recipients.len()
+        ));
+        self.envelopes.push(envelope.clone());
+    }
+
+    async fn authenticate(
+        &mut self,
+        _session: &Session<()>,
+        credentials: Credentials,
+    ) -> Result<String, Reply> {
+        if credentials.username() == "user" && credentials.verify_password("pencil") {
+            Ok("user".into())
+        } else {
+            Err(Reply::auth_failed())
+        }
+    }
+
+    async fn xforward_allowed(&mut self, session: &Session<()>) -> bool {
+        self.trusted || loopback(session)
+    }
+
+    async fn xforward(
+        &mut self,
+        _session: &Session<()>,
+        attributes: &Xforward,
+    ) -> Option<Response> {
+        self.events.push(format!("xforward {:?}", attributes));
+        self.xforward_response.clone()
+    }
+
+    async fn xclient_allowed(&mut self, session: &Session<()>) -> bool {
+        self.trusted || loopback(session)
+    }
+
+    async fn xclient(&mut self, _session: &Session<()>, attributes: &Xclient) -> Option<Response> {
+        self.events.push(format!("xclient {:?}", attributes));
+        self.xclient_response.clone()
+    }
+}
+
+fn loopback(session: &Session<()>) -> bool {
+    session
+        .socket_peer_addr()
+        .is_some_and(|addr| addr.ip().is_loopback())
+}
+
+/// A scripted session.
+pub struct Test {
+    pub config: Config,
+    pub handler: TestHandler,
+    pub peer_addr: SocketAddr,
+    /// Client input, one read per segment.
+    pub input: Vec<Vec<u8>>,
+    /// Block instead of reading EOF after the input.
+    pub hang: bool,
+    /// Serve with `smtps_server` instead of `smtp_session`.
+    pub smtps: bool,
+    pub fail_shutdown: bool,
+    /// Client not reading replies for a while.
+    pub stall_writes: Option<Duration>,
+    /// Never complete the TLS handshake.
+    pub hang_handshake: bool,
+}
+
+impl Default for Test {
+    fn default() -> Self {
+        Self {
+            config: Config::default(),
+            handler: TestHandler::default(),
+            peer_addr: "192.0.2.1:40000".parse().unwrap(),
+            input: Vec::new(),
+            hang: false,
+            smtps: false,
+            fail_shutdown: false,
+            hang_handshake: false,
+            stall_writes: None,
+        }
+    }
+}
+
+/// What the server did with a scripted session.
+pub struct Outcome {
+    pub output: String,
+    /// Number of writes to the socket.
+    pub writes: usize,
+    pub result: Result<(), ServerError>,
+    pub handler: TestHandler,
+    pub session: Session<()>,
+}
+
+impl Outcome {
+    /// Codes of the replies sent, one per reply.
+    pub fn codes(&self) -> Vec<u16> {
+        self.last_lines()
+            .iter()
+            .map(|line| line[..3].parse().unwrap())
+            .collect()
+    }
+
+    /// Last line of every reply.
+    pub fn last_lines(&self) -> Vec<&str> {
+        self.output
+            .split("\r\n")
+            .filter(|line| line.len() >= 3 && line.as_bytes().get(3) != Some(&b'-'))
+            .collect()
+    }
+
+    /// Whether a reply line contains `text`.
+    pub fn replied(&self, text: &str) -> bool {
+        self.output.split("\r\n").any(|line| line.contains(text))
+    }
+}
+
+/// Split a client script into segments.
+pub fn segments(input: &[&str]) -> Vec<Vec<u8>> {
+    input.iter().map(|s| s.as_bytes().to_vec()).collect()
+}
+
+pub fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new()
+        .basic_scheduler()
+        .enable_time()
+        .build()
+        .unwrap()
+}
+
+impl Test {
+    /// A session with `input` sent one segment at a time.
+    pub fn new(input: &[&str]) -> Self {
+        Self {
+            input: segments(input),
+            ..Self::default()
+        }
+    }
+
+    pub fn run(self) -> Outcome {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let writes = Arc::new(AtomicUsize::new(0));
+        let socket = MockSocket {
+            input: self.input.into(),
+            hang: self.hang,
+            fail_shutdown: self.fail_shutdown,
+            stall_writes: self.stall_writes,
+            stall: None,
+            output: output.clone(),
+            writes: writes.clone(),
+        };
+        let mut handler = self.handler;
+        let mut session = Session::new(
+            Some(self.peer_addr),
+            Some("198.51.100.1:25".parse().unwrap()),
+            (),
+        );
+        let config = self.config;
+        let smtps = self.smtps;
+        let acceptor = PlainAcceptor {
+            hang: self.hang_handshake,
+        };
+
+        let result = runtime().block_on(async {
+            let (_shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+            let mut shutdown = shutdown_rx.map_err(|_| ()).fuse();
+
+            if smtps {
+                smtps_server(
+                    socket,
+                    &mut handler,
+                    &mut session,
+                    &config,
+                    &mut shutdown,
+                    &acceptor,
+                )
+                .await
+                .map(|_| ())
+            } else {
+                smtp_session(
+                    socket,
+                    &mut handler,
+                    &mut session,
+                    &config,
+                    &mut shutdown,
+                    &acceptor,
+                )
+                .await
+            }
+        });
+
+        let output = String::from_utf8_lossy(&output.lock().unwrap()).into_owned();
+        Outcome {
+            output,
+            writes: writes.load(Ordering::SeqCst),
+            result,
+            handler,
+            session,
+        }
+    }
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use common::Test;
+use smtpbis::{Config, DsnNotify, LineCodec, Ret};
+
+fn dsn_test(input: &[&str]) -> Test {
+    let mut test = Test::new(input);
+    test.config = Config {
+        enable_dsn: true,
+        ..Config::default()
+    };
+    test
+}
+
+#[test]
+fn request_in_envelope() {
+    let out = dsn_test(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org> RET=HDRS ENVID=QQ314159\r\n",
+        "RCPT TO:<b@example.org> NOTIFY=SUCCESS,FAILURE ORCPT=rfc822;b@example.org\r\n",
+        "RCPT TO:<c@example.org>\r\n",
+        "RCPT TO:<d@zzz.example> NOTIFY=NEVER\r\n",
+        "RSET\r\n",
+    ])
+    .run();
+
+    assert!(out.replied("250-DSN"));
+    assert_eq!(out.codes(), [220, 250, 250, 250, 250, 550, 250]);
+
+    let dsn = out.handler.envelopes[0].dsn.clone().unwrap();
+    assert_eq!(dsn.ret, Some(Ret::Hdrs));
+    assert_eq!(dsn.envid.as_deref(), Some("QQ314159"));
+    // Rejected recipients are left out.
+    assert_eq!(dsn.recipients.len(), 2);
+    assert_eq!(
+        dsn.recipients[0].notify,
+        Some(DsnNotify::On {
+            success: true,
+            failure: true,
+            delay: false,
+        })
+    );
+    assert_eq!(
+        dsn.recipients[0].orcpt.as_ref().unwrap().address,
+        "b@example.org"
+    );
+    assert_eq!(dsn.recipients[1].notify, None);
+}
+
+#[test]
+fn invalid_parameters() {
+    let orcpt = format!(
+        "RCPT TO:<b@example.org> ORCPT=rfc822;{}@example.org\r\n",
+        "b".repeat(500)
+    );
+    let mut test = dsn_test(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org> RET=NONE\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RCPT TO:<b@example.org> NOTIFY=NEVER,SUCCESS\r\n",
+        "RCPT TO:<b@example.org> ORCPT=rfc822;not-an-address\r\n",
+        &orcpt,
+        "RCPT TO:<b@example.org> ORCPT=x-local;anything\r\n",
+    ]);
+    test.config.line_codec = LineCodec::builder().max_command_length(1024).build();
+    let out = test.run();
+
+    assert_eq!(out.codes(), [220, 250, 501, 250, 501, 501, 501, 250]);
+}
+
+#[test]
+fn never_combined() {
+    let out = dsn_test(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RCPT TO:<b@example.org> NOTIFY=NEVER,SUCCESS\r\n",
+        "RCPT TO:<b@example.org> NOTIFY=delay,never\r\n",
+        "RCPT TO:<b@example.org> NOTIFY=NEVER\r\n",
+    ])
+    .run();
+
+    assert_eq!(
+        out.last_lines()[3..],
+        [
+            "501 5.5.4 NOTIFY=NEVER cannot be combined with other values",
+            "501 5.5.4 NOTIFY=NEVER cannot be combined with other values",
+            "250 OK",
+        ]
+    );
+}
+
+#[test]
+fn disabled() {
+    let out = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org> RET=FULL\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RCPT TO:<b@example.org> NOTIFY=NEVER\r\n",
+        "RCPT TO:<b@example.org>\r\n",
+        "RSET\r\n",
+    ])
+    .run();
+
+    assert!(!out.replied("250-DSN"));
+    assert_eq!(out.codes(), [220, 250, 555, 250, 555, 250, 250]);
+    assert_eq!(out.handler.envelopes[0].dsn, None);
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::Test;
+use smtpbis::{Reply, ServerError};
+
+fn delayed(input: &[&str]) -> Test {
+    let mut test = Test::new(input);
+    test.config.greeting_delay = Some(Duration::from_millis(200));
+    test
+}
+
+#[test]
+fn reports_early_talker() {
+    let out = delayed(&["EHLO client.example.org\r\nMAIL FROM:<a@example.org>\r\n"]).run();
+
+    // The input is kept for the session.
+    assert_eq!(out.codes(), [220, 250, 250]);
+    assert!(out.session.early_talker());
+    assert!(out
+        .handler
+        .has_event("early talker EHLO client.example.org\r\nMAIL FROM:<a@example.org>\r\n"));
+}
+
+#[test]
+fn rejects_early_talker() {
+    let mut test = delayed(&["EHLO client.example.org\r\n"]);
+    test.handler.early_talker_reply = Some(Reply::new(554, None, "Too eager"));
+    let out = test.run();
+
+    assert_eq!(out.output, "554 Too eager\r\n");
+    assert!(out.session.early_talker());
+    assert!(out.result.is_ok());
+}
+
+#[test]
+fn patient_client() {
+    let mut test = delayed(&[]);
+    test.hang = true;
+    test.config.timeouts.greeting = Duration::from_millis(100);
+    let start = Instant::now();
+    let out = test.run();
+
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    assert_eq!(out.codes(), [220, 421]);
+    assert!(matches!(out.result, Err(ServerError::Timeout)));
+    assert!(!out.session.early_talker());
+    assert!(out.handler.events.is_empty());
+}
+
+#[test]
+fn disconnect_before_banner() {
+    let out = delayed(&[]).run();
+
+    assert!(out.output.is_empty());
+    assert!(matches!(out.result, Err(ServerError::EOF)));
+    assert!(!out.session.early_talker());
+}
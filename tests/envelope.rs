@@ -0,0 +1,105 @@
+mod common;
+
+use common::Test;
+
+#[test]
+fn collects_accepted_recipients() {
+    let mut test = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org> SIZE=100\r\n",
+        "RCPT TO:<b@example.org>\r\n",
+        "RCPT TO:<c@zz.example.org>\r\n",
+        "RCPT TO:<d@example.org>\r\n",
+        "DATA\r\n",
+        "Subject: test\r\n\r\nbody\r\n.\r\n",
+    ]);
+    test.config.max_message_size = Some(1000);
+    let out = test.run();
+
+    assert_eq!(out.codes(), [220, 250, 250, 250, 550, 250, 354, 250]);
+    assert_eq!(
+        out.handler.events.last().unwrap(),
+        "transaction Committed 2"
+    );
+    let envelope = &out.handler.envelopes[0];
+    assert_eq!(
+        envelope.reverse_path.as_ref().unwrap().to_string(),
+        "<a@example.org>"
+    );
+    assert!(envelope.mail_params.size == Some(100));
+    let recipients: Vec<_> = envelope
+        .recipients
+        .iter()
+        .map(|r| r.path.to_string())
+        .collect();
+    assert_eq!(recipients, ["<b@example.org>", "<d@example.org>"]);
+    assert_eq!(envelope.dsn, None);
+}
+
+#[test]
+fn reports_aborted_transactions() {
+    let mut test = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RSET\r\n",
+        "MAIL FROM:<b@example.org>\r\n",
+        "RCPT TO:<c@example.org>\r\n",
+        "DATA\r\n",
+        "body\r\n.\r\n",
+        "MAIL FROM:<d@example.org>\r\n",
+        "RCPT TO:<e@example.org>\r\n",
+    ]);
+    test.handler.message_response = Some(smtpbis::Reply::new(554, None, "Rejected").into());
+    let out = test.run();
+
+    assert_eq!(
+        out.codes(),
+        [220, 250, 250, 250, 250, 250, 354, 554, 250, 250]
+    );
+    let ended: Vec<_> = out
+        .handler
+        .events
+        .iter()
+        .filter(|e| e.starts_with("transaction"))
+        .collect();
+    assert_eq!(
+        ended,
+        [
+            "transaction Aborted 0",
+            "transaction Aborted 1",
+            "transaction Aborted 1",
+        ]
+    );
+    let senders: Vec<_> = out
+        .handler
+        .envelopes
+        .iter()
+        .map(|e| e.reverse_path.as_ref().unwrap().to_string())
+        .collect();
+    assert_eq!(
+        senders,
+        ["<a@example.org>", "<b@example.org>", "<d@example.org>"]
+    );
+}
+
+#[test]
+fn empty_between_transactions() {
+    let out = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<>\r\n",
+        "RCPT TO:<b@example.org>\r\n",
+        "DATA\r\n",
+        ".\r\n",
+        "MAIL FROM:<c@example.org>\r\n",
+        "RCPT TO:<d@example.org>\r\n",
+        "DATA\r\n",
+        ".\r\n",
+    ])
+    .run();
+
+    assert_eq!(out.handler.envelopes.len(), 2);
+    let second = &out.handler.envelopes[1];
+    assert_eq!(second.recipients.len(), 1);
+    assert_eq!(second.recipients[0].path.to_string(), "<d@example.org>");
+    assert!(out.handler.has_event("transaction Committed 1"));
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use common::Test;
+
+#[test]
+fn mail_requires_greeting() {
+    let out = Test::new(&[
+        "MAIL FROM:<a@example.org>\r\n",
+        "HELO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+    ])
+    .run();
+
+    assert_eq!(out.codes(), [220, 503, 250, 250]);
+}
+
+#[test]
+fn lenient_greeting() {
+    let mut test = Test::new(&["MAIL FROM:<a@example.org>\r\n"]);
+    test.config.lenient_greeting = true;
+
+    assert_eq!(test.run().codes(), [220, 250]);
+}
+
+#[test]
+fn helo_has_no_extensions() {
+    let mut test = Test::new(&[
+        "HELO client.example.org\r\n",
+        "MAIL FROM:<a@example.org> SIZE=100\r\n",
+    ]);
+    test.config.max_message_size = Some(1000);
+
+    assert_eq!(test.run().codes(), [220, 250, 555]);
+}
+
+#[test]
+fn greeting_aborts_transaction() {
+    let out = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RCPT TO:<b@example.org>\r\n",
+        "EHLO client.example.org\r\n",
+        "RCPT TO:<b@example.org>\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+    ])
+    .run();
+
+    assert_eq!(out.codes(), [220, 250, 250, 250, 250, 503, 250]);
+    assert!(out.handler.has_event("transaction Aborted 1"));
+    assert_eq!(
+        out.session.helo().map(ToString::to_string).as_deref(),
+        Some("client.example.org")
+    );
+}
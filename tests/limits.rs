@@ -0,0 +1,109 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::Test;
+use smtpbis::{ErrorLimit, ServerError};
+
+fn limit(soft: Option<u64>, hard: Option<u64>) -> ErrorLimit {
+    ErrorLimit { soft, hard }
+}
+
+#[test]
+fn hard_syntax_error_limit() {
+    let mut test = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:broken\r\n",
+        "RCPT TO:broken\r\n",
+        "NOOP\r\n",
+    ]);
+    test.config.limits.syntax_errors = limit(None, Some(2));
+    let out = test.run();
+
+    assert_eq!(out.codes(), [220, 250, 500, 421]);
+    assert!(out.replied("421 4.7.0 Too many errors"));
+    assert!(matches!(out.result, Err(ServerError::LimitExceeded)));
+    assert_eq!(out.session.syntax_errors(), 2);
+    // The 421 goes through the tarpit like other error replies.
+    assert_eq!(out.session.error_replies(), 2);
+}
+
+#[test]
+fn hard_unknown_command_limit() {
+    let mut test = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "FOO\r\n",
+        "BAR\r\n",
+        "NOOP\r\n",
+        "BAZ\r\n",
+    ]);
+    test.config.limits.unknown_commands = limit(None, Some(3));
+    let out = test.run();
+
+    // NOOP is not counted.
+    assert_eq!(out.codes(), [220, 250, 500, 500, 502, 421]);
+    assert_eq!(out.session.unknown_commands(), 3);
+}
+
+#[test]
+fn soft_limit_delays() {
+    let mut test = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RCPT TO:<a@zz.example.org>\r\n",
+        "RCPT TO:<b@zz.example.org>\r\n",
+        "RCPT TO:<c@zz.example.org>\r\n",
+    ]);
+    test.config.limits.rejected_recipients = limit(Some(1), None);
+    test.config.limits.soft_delay = Duration::from_millis(100);
+    let start = Instant::now();
+    let out = test.run();
+
+    // The second and third rejections are delayed.
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    assert_eq!(out.codes(), [220, 250, 250, 550, 550, 550]);
+    assert_eq!(out.session.rejected_recipients(), 3);
+}
+
+#[test]
+fn max_recipients() {
+    let mut test = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RCPT TO:<a@example.org>\r\n",
+        "RCPT TO:<b@example.org>\r\n",
+        "RCPT TO:<c@example.org>\r\n",
+        "DATA\r\n",
+        "body\r\n.\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RCPT TO:<d@example.org>\r\n",
+    ]);
+    test.config.limits.max_recipients = Some(2);
+    let out = test.run();
+
+    assert_eq!(
+        out.codes(),
+        [220, 250, 250, 250, 250, 452, 354, 250, 250, 250]
+    );
+    assert!(out.replied("452 4.5.3"));
+    assert_eq!(out.handler.envelopes[0].recipients.len(), 2);
+}
+
+#[test]
+fn max_transactions() {
+    let mut test = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RSET\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RSET\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+    ]);
+    test.config.limits.max_transactions = Some(2);
+    let out = test.run();
+
+    assert_eq!(out.codes(), [220, 250, 250, 250, 250, 250, 421]);
+    assert!(out.replied("421 4.7.0 Too many transactions"));
+    assert_eq!(out.session.transactions(), 2);
+    assert_eq!(out.session.error_replies(), 1);
+}
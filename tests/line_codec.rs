@@ -0,0 +1,54 @@
+mod common;
+
+use common::Test;
+use smtpbis::LineCodec;
+
+fn session(codec: LineCodec, input: &[&str]) -> common::Outcome {
+    let mut test = Test::new(input);
+    test.config.line_codec = codec;
+    test.run()
+}
+
+#[test]
+fn configured_command_length() {
+    let out = session(
+        LineCodec::builder().max_command_length(20).build(),
+        &["HELO client.example.org\r\n", "HELO a.example.org\r\n"],
+    );
+
+    assert_eq!(out.codes(), [220, 500, 250]);
+}
+
+#[test]
+fn configured_text_length() {
+    let out = session(
+        LineCodec::builder().max_text_length(10).build(),
+        &[
+            "HELO client.example.org\r\n",
+            "MAIL FROM:<a@example.org>\r\n",
+            "RCPT TO:<b@example.org>\r\n",
+            "DATA\r\n",
+            "12345678\r\n123456789\r\n.\r\n",
+        ],
+    );
+
+    assert_eq!(out.codes(), [220, 250, 250, 250, 354, 554]);
+}
+
+#[test]
+fn configured_chunk_size() {
+    let body = "x".repeat(100);
+    let command = format!("BDAT 100 LAST\r\n{}", body);
+    let out = session(
+        LineCodec::builder().max_chunk_size(16).build(),
+        &[
+            "EHLO client.example.org\r\n",
+            "MAIL FROM:<a@example.org>\r\n",
+            "RCPT TO:<b@example.org>\r\n",
+            &command,
+        ],
+    );
+
+    assert_eq!(out.codes(), [220, 250, 250, 250, 250]);
+    assert_eq!(out.handler.message(0), body.as_bytes());
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use common::Test;
+
+fn long(prefix: &str, length: usize) -> String {
+    format!("{}{}\r\n", prefix, "x".repeat(length - prefix.len() - 2))
+}
+
+#[test]
+fn long_command_recovers() {
+    let line = long("NOOP ", 600);
+    let out = Test::new(&[
+        "EHLO client.example.org\r\n",
+        &line[..300],
+        &line[300..],
+        "MAIL FROM:<a@example.org>\r\n",
+    ])
+    .run();
+
+    assert_eq!(out.codes(), [220, 250, 500, 250]);
+    assert!(out.replied("500 5.5.2 Line too long"));
+    assert_eq!(out.session.syntax_errors(), 1);
+}
+
+#[test]
+fn long_data_line_keeps_pipelined_commands() {
+    // The long line is the last one, so the end of data may be read
+    // by a handler looking past the error.
+    for read_past_errors in [false, true] {
+        let body = format!(
+            "first\r\n{}.\r\nMAIL FROM:<c@example.org>\r\nRCPT TO:<d@example.org>\r\n",
+            long("", 1200)
+        );
+        let mut test = Test::new(&[
+            "EHLO client.example.org\r\n",
+            "MAIL FROM:<a@example.org>\r\n",
+            "RCPT TO:<b@example.org>\r\n",
+            "DATA\r\n",
+            &body,
+        ]);
+        test.handler.read_past_errors = read_past_errors;
+        let out = test.run();
+
+        assert_eq!(out.codes(), [220, 250, 250, 250, 354, 554, 250, 250]);
+        assert!(out.replied("554 5.6.0"));
+        assert!(out.handler.messages.is_empty());
+        assert_eq!(out.session.transactions(), 2);
+    }
+}
+
+#[test]
+fn text_lines_longer_than_commands() {
+    let body = format!("{}.\r\n", long("", 1000));
+    let out = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RCPT TO:<b@example.org>\r\n",
+        "DATA\r\n",
+        &body,
+    ])
+    .run();
+
+    assert_eq!(out.codes(), [220, 250, 250, 250, 354, 250]);
+    assert_eq!(out.handler.message(0).len(), 1000);
+}
+
+#[test]
+fn auth_allows_long_lines() {
+    let auth = long("AUTH PLAIN ", 2000);
+    let mut test = Test::new(&["HELO client.example.org\r\n", &auth]);
+    test.config.auth_mechanisms = vec!["PLAIN".into()];
+    // No extensions with HELO.
+    assert_eq!(test.run().codes(), [220, 250, 500]);
+
+    let mut test = Test::new(&["EHLO client.example.org\r\n", &auth]);
+    test.config.auth_mechanisms = vec!["PLAIN".into()];
+    assert_eq!(test.run().codes(), [220, 250, 501]);
+}
+
+#[test]
+fn auth_allowance_only_for_auth() {
+    let noop = long("NOOP ", 2000);
+    let mut test = Test::new(&["EHLO client.example.org\r\n", &noop]);
+    test.config.auth_mechanisms = vec!["PLAIN".into()];
+    let out = test.run();
+
+    assert_eq!(out.codes(), [220, 250, 500]);
+    assert!(out.replied("500 5.5.2 Line too long"));
+}
+
+#[test]
+fn sasl_response_allows_long_lines() {
+    let response = long("", 2000);
+    let mut test = Test::new(&["EHLO client.example.org\r\n", "AUTH PLAIN\r\n", &response]);
+    test.config.auth_mechanisms = vec!["PLAIN".into()];
+    let out = test.run();
+
+    assert_eq!(out.codes(), [220, 250, 334, 501]);
+    assert!(!out.replied("Line too long"));
+}
+
+#[test]
+fn dsn_allows_long_recipients() {
+    let rcpt = format!(
+        "RCPT TO:<b@example.org> ORCPT=rfc822;{}@example.org\r\n",
+        "x".repeat(480)
+    );
+    let mut test = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        &rcpt,
+    ]);
+    test.config.enable_dsn = true;
+    assert_eq!(test.run().codes(), [220, 250, 250, 250]);
+
+    let mut test = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        &rcpt,
+    ]);
+    test.config.enable_dsn = false;
+    assert_eq!(test.run().codes(), [220, 250, 250, 500]);
+}
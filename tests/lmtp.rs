@@ -0,0 +1,98 @@
+mod common;
+
+use common::Test;
+use smtpbis::Config;
+
+fn lmtp_config() -> Config {
+    Config {
+        lmtp: true,
+        ..Config::default()
+    }
+}
+
+#[test]
+fn data_reply_per_recipient() {
+    let mut test = Test::new(&[
+        "LHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RCPT TO:<b@example.org>\r\n",
+        "RCPT TO:<c@zzz.example>\r\n",
+        "RCPT TO:<d@example.org>\r\n",
+        "DATA\r\n",
+        "Subject: test\r\n\r\nbody\r\n.\r\n",
+        "QUIT\r\n",
+    ]);
+    test.config = lmtp_config();
+    let out = test.run();
+
+    assert!(out.replied("220 localhost LMTP"));
+    assert_eq!(
+        out.codes(),
+        [220, 250, 250, 250, 550, 250, 354, 250, 250, 221]
+    );
+    assert!(out.handler.has_event("transaction Committed 2"));
+}
+
+#[test]
+fn bdat_reply_per_recipient() {
+    let mut test = Test::new(&[
+        "LHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RCPT TO:<b@example.org>\r\n",
+        "RCPT TO:<d@example.org>\r\n",
+        "BDAT 6\r\nhello ",
+        "BDAT 5 LAST\r\nworld",
+        "QUIT\r\n",
+    ]);
+    test.config = lmtp_config();
+    let out = test.run();
+
+    assert_eq!(out.codes(), [220, 250, 250, 250, 250, 250, 250, 250, 221]);
+    assert_eq!(out.handler.message(0), b"hello world");
+}
+
+#[test]
+fn smtp_greeting_refused() {
+    let mut test = Test::new(&["EHLO client.example.org\r\n", "HELO client.example.org\r\n"]);
+    test.config = lmtp_config();
+    let out = test.run();
+
+    assert_eq!(out.codes(), [220, 500, 500]);
+}
+
+#[test]
+fn bdat_last_too_large_ends_transaction() {
+    let mut test = Test::new(&[
+        "LHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RCPT TO:<b@example.org>\r\n",
+        "RCPT TO:<d@example.org>\r\n",
+        "BDAT 20 LAST\r\n01234567890123456789",
+        "MAIL FROM:<a@example.org>\r\n",
+    ]);
+    test.config = Config {
+        max_message_size: Some(10),
+        ..lmtp_config()
+    };
+    let out = test.run();
+
+    assert_eq!(out.codes(), [220, 250, 250, 250, 250, 552, 552, 250]);
+    assert!(out.handler.has_event("transaction Aborted 2"));
+}
+
+#[test]
+fn bdat_last_after_failed_chunk_ends_transaction() {
+    let mut test = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RCPT TO:<b@example.org>\r\n",
+        "BDAT 20\r\n01234567890123456789",
+        "BDAT 2 LAST\r\n01",
+        "MAIL FROM:<a@example.org>\r\n",
+    ]);
+    test.config.max_message_size = Some(10);
+    let out = test.run();
+
+    assert_eq!(out.codes(), [220, 250, 250, 250, 552, 503, 250]);
+    assert!(out.handler.has_event("transaction Aborted 1"));
+}
@@ -0,0 +1,115 @@
+mod common;
+
+use common::Test;
+
+fn unified(input: &[&str]) -> Test {
+    let mut test = Test::new(input);
+    test.config.unified_message = true;
+    test.config.max_message_size = Some(1000);
+    test
+}
+
+#[test]
+fn data_message() {
+    let out = unified(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RCPT TO:<b@example.org>\r\n",
+        "DATA\r\n",
+        "one\r\n..two\r\n.\r\n",
+    ])
+    .run();
+
+    assert_eq!(out.codes(), [220, 250, 250, 250, 354, 250]);
+    assert_eq!(out.handler.messages.len(), 1);
+    assert_eq!(out.handler.message(0), b"one\r\n.two\r\n");
+}
+
+#[test]
+fn bdat_message() {
+    let out = unified(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RCPT TO:<b@example.org>\r\n",
+        "BDAT 5\r\none\r\n",
+        "BDAT 0\r\n",
+        "BDAT 6 LAST\r\n..two\n",
+        "MAIL FROM:<c@example.org>\r\n",
+    ])
+    .run();
+
+    assert_eq!(out.codes(), [220, 250, 250, 250, 250, 250, 250, 250]);
+    assert_eq!(out.handler.messages.len(), 1);
+    assert_eq!(out.handler.message(0), b"one\r\n..two\n");
+    assert!(out.handler.has_event("transaction Committed 1"));
+}
+
+#[test]
+fn bdat_message_too_large() {
+    let mut test = unified(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RCPT TO:<b@example.org>\r\n",
+        "BDAT 5\r\none\r\n",
+        "BDAT 10 LAST\r\n0123456789",
+    ]);
+    test.config.max_message_size = Some(10);
+    let out = test.run();
+
+    assert_eq!(out.codes(), [220, 250, 250, 250, 250, 552]);
+    assert!(out.handler.messages.is_empty());
+    assert!(out.handler.has_event("transaction Aborted 1"));
+}
+
+#[test]
+fn chunking_without_size_limit() {
+    let mut test = unified(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RCPT TO:<b@example.org>\r\n",
+        "BDAT 5 LAST\r\none\r\n",
+    ]);
+    test.config.max_message_size = None;
+    let out = test.run();
+
+    assert!(out.replied("CHUNKING"));
+    assert_eq!(out.codes(), [220, 250, 250, 250, 250]);
+    assert_eq!(out.handler.message(0), b"one\r\n");
+}
+
+#[test]
+fn bdat_message_later_chunk_too_large() {
+    let mut test = unified(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RCPT TO:<b@example.org>\r\n",
+        "BDAT 5\r\none\r\n",
+        "BDAT 10\r\n0123456789",
+        "BDAT 0 LAST\r\n",
+        "RSET\r\n",
+    ]);
+    test.config.max_message_size = Some(10);
+    let out = test.run();
+
+    assert_eq!(out.codes(), [220, 250, 250, 250, 250, 552, 503, 250]);
+    assert!(out.handler.messages.is_empty());
+    assert!(out.handler.has_event("body error MessageTooLarge"));
+}
+
+#[test]
+fn bdat_message_interrupted() {
+    let out = unified(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RCPT TO:<b@example.org>\r\n",
+        "BDAT 5\r\none\r\n",
+        "RSET\r\n",
+        "MAIL FROM:<c@example.org>\r\n",
+    ])
+    .run();
+
+    assert_eq!(out.codes(), [220, 250, 250, 250, 250, 250, 250]);
+    assert!(out.handler.messages.is_empty());
+    assert!(out.handler.has_event("body error DataAbort"));
+    assert!(out.handler.has_event("transaction Aborted 1"));
+}
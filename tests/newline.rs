@@ -0,0 +1,92 @@
+mod common;
+
+use common::Test;
+use smtpbis::BareNewlinePolicy;
+
+fn session(policy: BareNewlinePolicy, body: &str) -> Test {
+    let mut test = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RCPT TO:<b@example.org>\r\n",
+        "DATA\r\n",
+        body,
+    ]);
+    test.config.bare_newline = policy;
+    test
+}
+
+#[test]
+fn rejects_bare_newlines() {
+    let out = session(
+        BareNewlinePolicy::Reject,
+        "hello\n.\nsmuggled\r\n.\r\nMAIL FROM:<c@example.org>\r\n",
+    )
+    .run();
+
+    assert_eq!(out.codes(), [220, 250, 250, 250, 354, 550, 250]);
+    assert!(out.replied("550 5.6.0"));
+    assert!(out.handler.messages.is_empty());
+    assert!(out
+        .handler
+        .has_event("bare newline BareNewline { byte: 10, line: 1, end_of_data: true }"));
+    assert!(out.handler.has_event("transaction Aborted 1"));
+}
+
+#[test]
+fn reject_keeps_pipelined_commands() {
+    // The offending line is the last one, so the end of data may be
+    // read by a handler looking past the error.
+    for read_past_errors in [false, true] {
+        let mut test = session(
+            BareNewlinePolicy::Reject,
+            "first\r\nlast\n\r\n.\r\nMAIL FROM:<c@example.org>\r\nRCPT TO:<d@example.org>\r\n",
+        );
+        test.handler.read_past_errors = read_past_errors;
+        let out = test.run();
+
+        assert_eq!(out.codes(), [220, 250, 250, 250, 354, 550, 250, 250]);
+        assert_eq!(out.session.transactions(), 2);
+    }
+}
+
+#[test]
+fn normalizes_bare_newlines() {
+    let out = session(
+        BareNewlinePolicy::Normalize,
+        "hello\n.\nsmuggled\r\n..x\r.y\r\n.\r\n",
+    )
+    .run();
+
+    assert_eq!(out.codes(), [220, 250, 250, 250, 354, 250]);
+    // Split lines are unstuffed one by one and the smuggled end of
+    // data stays in the message.
+    assert_eq!(
+        out.handler.messages[0],
+        [
+            &b"hello\r\n"[..],
+            b".\r\n",
+            b"smuggled\r\n",
+            b".x\r\n",
+            b"y\r\n"
+        ]
+    );
+    assert_eq!(
+        out.handler
+            .events
+            .iter()
+            .filter(|e| e.starts_with("bare newline"))
+            .count(),
+        3
+    );
+}
+
+#[test]
+fn passes_bare_newlines_through() {
+    let out = session(BareNewlinePolicy::PassThrough, "a\nb\r\n..c\r\n.\r\n").run();
+
+    assert_eq!(out.codes(), [220, 250, 250, 250, 354, 250]);
+    assert_eq!(out.handler.message(0), b"a\nb\r\n.c\r\n");
+    assert!(out
+        .handler
+        .has_event("bare newline BareNewline { byte: 10, line: 1, end_of_data: false }"));
+}
@@ -0,0 +1,137 @@
+use rustyknife::rfc5321::Param;
+
+use smtpbis::{Body, EhloKeywords, MailParams, Notify, RcptParams, Reply, Ret};
+
+fn extensions(keywords: &[&str]) -> EhloKeywords {
+    keywords.iter().map(|kw| ((*kw).into(), None)).collect()
+}
+
+fn params(input: &[(&str, Option<&str>)]) -> Vec<Param> {
+    input
+        .iter()
+        .map(|(keyword, value)| Param::new(*keyword, *value).unwrap())
+        .collect()
+}
+
+fn code(reply: Reply) -> u16 {
+    reply.to_string()[..3].parse().unwrap()
+}
+
+fn mail(input: &[(&str, Option<&str>)], keywords: &[&str]) -> Result<MailParams, u16> {
+    MailParams::parse(params(input), &extensions(keywords)).map_err(code)
+}
+
+fn rcpt(input: &[(&str, Option<&str>)], keywords: &[&str]) -> Result<RcptParams, u16> {
+    RcptParams::parse(params(input), &extensions(keywords)).map_err(code)
+}
+
+#[test]
+fn mail_params() {
+    let parsed = mail(
+        &[
+            ("body", Some("8bitmime")),
+            ("SIZE", Some("1000")),
+            ("SMTPUTF8", None),
+            ("AUTH", Some("user+40example.org")),
+        ],
+        &["8BITMIME", "SIZE", "SMTPUTF8", "AUTH"],
+    )
+    .unwrap();
+
+    assert_eq!(parsed.body, Some(Body::EightBitMime));
+    assert_eq!(parsed.size, Some(1000));
+    assert!(parsed.smtputf8);
+    assert_eq!(parsed.auth.as_deref(), Some("user@example.org"));
+}
+
+#[test]
+fn mail_params_rejected() {
+    // Not advertised, duplicate, unknown.
+    assert_eq!(mail(&[("SIZE", Some("10"))], &[]), Err(555));
+    assert_eq!(
+        mail(&[("SIZE", Some("10")), ("SIZE", Some("10"))], &["SIZE"]),
+        Err(555)
+    );
+    assert_eq!(mail(&[("FOO", Some("bar"))], &["SIZE"]), Err(555));
+    // Bad values.
+    assert_eq!(mail(&[("SIZE", Some("ten"))], &["SIZE"]), Err(501));
+    assert_eq!(mail(&[("BODY", Some("9BIT"))], &["8BITMIME"]), Err(501));
+    assert_eq!(mail(&[("SMTPUTF8", Some("yes"))], &["SMTPUTF8"]), Err(501));
+    assert_eq!(mail(&[("AUTH", Some("a+zz"))], &["AUTH"]), Err(501));
+}
+
+#[test]
+fn dsn_mail_params() {
+    let parsed = mail(
+        &[("RET", Some("hdrs")), ("ENVID", Some("QQ314159+2Bx"))],
+        &["DSN"],
+    )
+    .unwrap();
+
+    assert_eq!(parsed.ret, Some(Ret::Hdrs));
+    assert_eq!(parsed.envid.as_deref(), Some("QQ314159+x"));
+
+    let envid = "x".repeat(100);
+    assert!(mail(&[("ENVID", Some(&envid))], &["DSN"]).is_ok());
+    let envid = "x".repeat(101);
+    assert_eq!(mail(&[("ENVID", Some(&envid))], &["DSN"]), Err(501));
+    assert_eq!(mail(&[("RET", Some("BODY"))], &["DSN"]), Err(501));
+    assert_eq!(mail(&[("RET", None)], &["DSN"]), Err(501));
+    assert_eq!(mail(&[("RET", Some("FULL"))], &[]), Err(555));
+}
+
+#[test]
+fn dsn_rcpt_params() {
+    let parsed = rcpt(
+        &[
+            ("NOTIFY", Some("success,DELAY")),
+            ("ORCPT", Some("rfc822;a+2Bb@example.org")),
+        ],
+        &["DSN"],
+    )
+    .unwrap();
+
+    assert_eq!(
+        parsed.notify,
+        Some(Notify {
+            never: false,
+            success: true,
+            failure: false,
+            delay: true,
+        })
+    );
+    let orcpt = parsed.orcpt.unwrap();
+    assert_eq!(orcpt.addr_type, "rfc822");
+    assert_eq!(orcpt.address, "a+b@example.org");
+
+    let never = rcpt(&[("NOTIFY", Some("NEVER"))], &["DSN"]).unwrap();
+    assert!(never.notify.unwrap().never);
+    assert_eq!(rcpt(&[("NOTIFY", Some("NEVER,DELAY"))], &["DSN"]), Err(501));
+    assert_eq!(rcpt(&[("NOTIFY", Some("SOMETIMES"))], &["DSN"]), Err(501));
+    assert_eq!(rcpt(&[("ORCPT", Some("rfc822"))], &["DSN"]), Err(501));
+    assert_eq!(rcpt(&[("NOTIFY", Some("NEVER"))], &[]), Err(555));
+}
+
+#[test]
+fn extra_params() {
+    let parsed = mail(
+        &[("mt-priority", Some("3")), ("REQUIRETLS", None)],
+        &["MT-PRIORITY", "REQUIRETLS"],
+    )
+    .unwrap();
+    assert_eq!(
+        parsed.extra,
+        [
+            ("MT-PRIORITY".to_string(), Some("3".to_string())),
+            ("REQUIRETLS".to_string(), None)
+        ]
+    );
+
+    let parsed = rcpt(&[("RRVS", Some("2014-04-03T23:01:00Z"))], &["RRVS"]).unwrap();
+    assert_eq!(parsed.extra.len(), 1);
+    assert_eq!(
+        rcpt(&[("RRVS", Some("a")), ("RRVS", Some("b"))], &["RRVS"]),
+        Err(555)
+    );
+    assert_eq!(rcpt(&[("RRVS", Some("a"))], &[]), Err(555));
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use common::Test;
+
+fn envelope(recipients: usize) -> String {
+    let mut group = "MAIL FROM:<a@example.org>\r\n".to_string();
+    for i in 0..recipients {
+        group += &format!("RCPT TO:<r{}@example.org>\r\n", i);
+    }
+    group + "DATA\r\n"
+}
+
+#[test]
+fn replies_batched_per_group() {
+    let envelope = envelope(10);
+    let out = Test::new(&[
+        "EHLO client.example.org\r\n",
+        &envelope,
+        "body\r\n.\r\n",
+        "QUIT\r\n",
+    ])
+    .run();
+
+    assert_eq!(out.codes().len(), 16);
+    assert_eq!(out.writes, 5);
+}
+
+#[test]
+fn lock_step_replies_written_one_by_one() {
+    let envelope = envelope(10);
+    let mut input = vec!["EHLO client.example.org\r\n"];
+    input.extend(envelope.split_inclusive('\n'));
+    input.extend(["body\r\n.\r\n", "QUIT\r\n"]);
+    let out = Test::new(&input).run();
+
+    assert_eq!(out.codes().len(), 16);
+    assert_eq!(out.writes, 16);
+}
+
+fn violations(out: &common::Outcome) -> Vec<&str> {
+    out.handler
+        .events
+        .iter()
+        .filter(|e| e.starts_with("pipelining"))
+        .map(String::as_str)
+        .collect()
+}
+
+#[test]
+fn input_after_group_end() {
+    for command in ["NOOP\r\n", "VRFY user\r\n", "EXPN list\r\n", "DATA\r\n"] {
+        let input = format!("{}RSET\r\n", command);
+        let out = Test::new(&["EHLO client.example.org\r\n", &input]).run();
+
+        let name = command.split([' ', '\r']).next().unwrap();
+        assert_eq!(
+            violations(&out),
+            [format!("pipelining AfterGroupEnd({:?})", name)]
+        );
+        assert!(out.session.improper_pipelining());
+    }
+}
+
+#[test]
+fn input_after_bdat_last() {
+    let out = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\nRCPT TO:<b@example.org>\r\nBDAT 5\r\nhello",
+        "BDAT 5 LAST\r\nworldRSET\r\n",
+    ])
+    .run();
+
+    assert_eq!(out.codes(), [220, 250, 250, 250, 250, 250, 250]);
+    assert_eq!(
+        violations(&out),
+        ["pipelining AfterGroupEnd(\"BDAT LAST\")"]
+    );
+}
+
+#[test]
+fn chunks_without_pipelining() {
+    let mut test = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RCPT TO:<b@example.org>\r\n",
+        "BDAT 5\r\nhello",
+        "BDAT 5 LAST\r\nworld",
+    ]);
+    test.handler.hidden_keywords = vec!["PIPELINING"];
+    let out = test.run();
+
+    assert!(!out.replied("PIPELINING"));
+    assert_eq!(out.codes(), [220, 250, 250, 250, 250, 250]);
+    assert_eq!(out.handler.message(0), b"helloworld");
+    assert!(violations(&out).is_empty());
+    assert!(!out.session.improper_pipelining());
+}
+
+#[test]
+fn not_advertised() {
+    let mut test = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\nRCPT TO:<b@example.org>\r\n",
+        "BDAT 5\r\nhelloBDAT 5 LAST\r\nworld",
+    ]);
+    test.handler.hidden_keywords = vec!["PIPELINING"];
+    let out = test.run();
+
+    assert_eq!(
+        violations(&out),
+        ["pipelining NotAdvertised", "pipelining NotAdvertised"]
+    );
+}
+
+#[test]
+fn rejects_violations() {
+    let mut test = Test::new(&["EHLO client.example.org\r\nNOOP\r\n"]);
+    test.config.pipelining = smtpbis::PipeliningPolicy::Reject;
+    let out = test.run();
+
+    assert_eq!(out.codes(), [220, 554]);
+    assert!(out.replied("554 5.5.0"));
+    assert_eq!(violations(&out), ["pipelining AfterGroupEnd(\"EHLO\")"]);
+}
@@ -0,0 +1,119 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{Test, TestHandler};
+use smtpbis::{ProxyProtocol, ServerError};
+
+const WAIT: Duration = Duration::from_millis(100);
+
+fn proxy_test(mode: ProxyProtocol, input: &[&str]) -> Test {
+    let mut test = Test::new(input);
+    test.config.proxy_protocol = mode;
+    test.handler = TestHandler::trusted();
+    test
+}
+
+#[test]
+fn header_applied() {
+    let out = proxy_test(
+        ProxyProtocol::Required(WAIT),
+        &[
+            "PROXY TCP4 203.0.113.5 198.51.100.7 40001 2525\r\n",
+            "EHLO client.example.org\r\n",
+        ],
+    )
+    .run();
+
+    assert_eq!(out.codes(), [220, 250]);
+    assert!(out.handler.has_event("proxy Some(203.0.113.5:40001)"));
+    assert_eq!(
+        out.session.peer_addr(),
+        Some("203.0.113.5:40001".parse().unwrap())
+    );
+    assert_eq!(
+        out.session.local_addr(),
+        Some("198.51.100.7:2525".parse().unwrap())
+    );
+    assert_eq!(
+        out.session.socket_peer_addr(),
+        Some("192.0.2.1:40000".parse().unwrap())
+    );
+}
+
+#[test]
+fn header_and_commands_in_one_segment() {
+    let out = proxy_test(
+        ProxyProtocol::Required(WAIT),
+        &["PROXY UNKNOWN\r\nEHLO client.example.org\r\n"],
+    )
+    .run();
+
+    assert_eq!(out.codes(), [220, 250]);
+    assert!(out.handler.has_event("proxy None"));
+}
+
+#[test]
+fn untrusted_peer() {
+    let mut test = proxy_test(
+        ProxyProtocol::Required(WAIT),
+        &["PROXY TCP4 127.0.0.1 198.51.100.7 40001 25\r\n"],
+    );
+    test.handler = TestHandler::default();
+    let out = test.run();
+
+    assert!(matches!(out.result, Err(ServerError::ProxyProtocol)));
+    assert_eq!(out.output, "");
+}
+
+/// An optional header from an untrusted peer is plain SMTP input, it
+/// cannot grant the trust of the address it claims.
+#[test]
+fn optional_untrusted_peer() {
+    let mut test = proxy_test(
+        ProxyProtocol::Optional(WAIT),
+        &[
+            "PROXY TCP4 127.0.0.1 198.51.100.7 40001 25\r\n",
+            "EHLO client.example.org\r\n",
+            "XCLIENT LOGIN=admin\r\n",
+        ],
+    );
+    test.handler = TestHandler::default();
+    let out = test.run();
+
+    assert_eq!(out.codes(), [220, 500, 250, 502]);
+    assert!(!out.replied("XCLIENT"));
+    assert_eq!(out.session.authenticated(), None);
+    assert_eq!(out.session.peer_addr(), out.session.socket_peer_addr());
+}
+
+#[test]
+fn optional_without_header() {
+    let mut test = proxy_test(ProxyProtocol::Optional(WAIT), &[]);
+    test.hang = true;
+    test.config.timeouts.greeting = WAIT * 2;
+    let start = Instant::now();
+    let out = test.run();
+
+    // The banner is only delayed.
+    assert!(start.elapsed() >= WAIT * 3);
+    assert_eq!(out.codes(), [220, 421]);
+    assert!(matches!(out.result, Err(ServerError::Timeout)));
+}
+
+#[test]
+fn partial_header_times_out() {
+    for mode in [ProxyProtocol::Required(WAIT), ProxyProtocol::Optional(WAIT)] {
+        let mut test = proxy_test(mode, &["PROXY TCP4 203.0.113.5"]);
+        test.hang = true;
+        let out = test.run();
+
+        assert!(matches!(out.result, Err(ServerError::ProxyProtocol)));
+        assert_eq!(out.output, "");
+    }
+
+    let mut test = proxy_test(ProxyProtocol::Required(WAIT), &[]);
+    test.hang = true;
+    let out = test.run();
+    assert!(matches!(out.result, Err(ServerError::ProxyProtocol)));
+}
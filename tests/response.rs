@@ -0,0 +1,140 @@
+mod common;
+
+use common::Test;
+use smtpbis::{Reply, Response};
+
+const ENVELOPE: [&str; 3] = [
+    "EHLO client.example.org\r\n",
+    "MAIL FROM:<a@example.org>\r\n",
+    "RCPT TO:<b@example.org>\r\n",
+];
+
+fn session(input: &[&str]) -> Test {
+    let mut script = ENVELOPE.to_vec();
+    script.extend(input);
+    Test::new(&script)
+}
+
+fn too_many() -> Response {
+    Response::disconnect(Reply::new(421, None, "Too many bad recipients"))
+}
+
+#[test]
+fn rcpt_disconnects() {
+    let mut test = session(&[
+        "RCPT TO:<c@zz.example.org>\r\n",
+        "RCPT TO:<d@example.org>\r\n",
+    ]);
+    test.handler.rcpt_rejection = Some(too_many());
+    let out = test.run();
+
+    assert_eq!(out.codes(), [220, 250, 250, 250, 421]);
+    assert!(out.replied("421 Too many bad recipients"));
+    assert!(out.result.is_ok());
+    assert!(out.handler.has_event("transaction Aborted 1"));
+}
+
+#[test]
+fn rcpt_resets_transaction() {
+    let mut test = session(&[
+        "RCPT TO:<c@zz.example.org>\r\n",
+        "RCPT TO:<d@example.org>\r\n",
+    ]);
+    test.handler.rcpt_rejection = Some(Response::reset_transaction(Reply::new(
+        550,
+        None,
+        "Start over",
+    )));
+    let out = test.run();
+
+    assert_eq!(out.codes(), [220, 250, 250, 250, 550, 503]);
+    assert!(out.handler.has_event("transaction Aborted 1"));
+}
+
+#[test]
+fn data_disconnects() {
+    let mut test = session(&["DATA\r\n", "body\r\n.\r\nMAIL FROM:<c@example.org>\r\n"]);
+    test.handler.message_response =
+        Some(Response::disconnect(Reply::new(250, None, "Queued, bye")));
+    let out = test.run();
+
+    assert_eq!(out.codes(), [220, 250, 250, 250, 354, 250]);
+    assert!(out.result.is_ok());
+    assert!(out.handler.has_event("transaction Committed 1"));
+    assert_eq!(out.session.transactions(), 1);
+}
+
+#[test]
+fn bdat_disconnects() {
+    let mut test = session(&["BDAT 4\r\nbody", "BDAT 0 LAST\r\n", "NOOP\r\n"]);
+    test.handler.message_response = Some(too_many());
+    let out = test.run();
+
+    assert_eq!(out.codes(), [220, 250, 250, 250, 250, 421]);
+    assert!(out.handler.has_event("transaction Aborted 1"));
+}
+
+#[test]
+fn lmtp_disconnects_after_every_reply() {
+    let mut test = session(&[
+        "RCPT TO:<c@example.org>\r\n",
+        "DATA\r\n",
+        "body\r\n.\r\n",
+        "NOOP\r\n",
+    ]);
+    test.input[0] = b"LHLO client.example.org\r\n".to_vec();
+    test.config.lmtp = true;
+    test.handler.message_response = Some(too_many());
+    let out = test.run();
+
+    assert_eq!(out.codes(), [220, 250, 250, 250, 250, 354, 421, 421]);
+    assert!(out.result.is_ok());
+}
+
+#[test]
+fn unified_message_disconnects() {
+    let mut test = session(&["BDAT 4\r\nbody", "BDAT 0 LAST\r\n", "NOOP\r\n"]);
+    test.config.unified_message = true;
+    test.config.max_message_size = Some(1000);
+    test.handler.message_response = Some(too_many());
+    let out = test.run();
+
+    assert_eq!(out.codes(), [220, 250, 250, 250, 250, 421]);
+    assert_eq!(out.handler.message(0), b"body");
+}
+
+#[test]
+fn xforward_disconnects() {
+    let mut test = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "XFORWARD NAME=spam.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+    ]);
+    test.handler.trusted = true;
+    test.handler.xforward_response = Some(Response::disconnect(Reply::new(421, None, "Go away")));
+    let out = test.run();
+
+    assert_eq!(out.codes(), [220, 250, 421]);
+    assert!(out.result.is_ok());
+}
+
+#[test]
+fn xclient_rejects() {
+    let mut test = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "XCLIENT ADDR=192.0.2.9\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+    ]);
+    test.handler.trusted = true;
+    test.handler.xclient_response = Some(Reply::new(550, None, "Not you").into());
+    let out = test.run();
+
+    // The session goes on without the new attributes.
+    assert_eq!(out.codes(), [220, 250, 550, 250]);
+    assert_eq!(out.session.peer_addr(), out.session.socket_peer_addr());
+
+    let mut test = Test::new(&["EHLO client.example.org\r\n", "XCLIENT ADDR=192.0.2.9\r\n"]);
+    test.handler.trusted = true;
+    test.handler.xclient_response = Some(Response::disconnect(Reply::new(421, None, "Go away")));
+    assert_eq!(test.run().codes(), [220, 250, 421]);
+}
@@ -0,0 +1,183 @@
+use std::sync::Arc;
+
+use sha2::Digest;
+
+use smtpbis::{
+    Credentials, SaslMechanism, SaslStep, ScramCredentialStore, ScramCredentials, ScramHash,
+    ScramSha1, ScramSha256,
+};
+
+struct Store<H> {
+    salt: &'static str,
+    _hash: std::marker::PhantomData<H>,
+}
+
+impl<H: ScramHash + Sync> ScramCredentialStore for Store<H> {
+    fn lookup(&self, mechanism: &str, username: &str) -> Option<ScramCredentials> {
+        assert_eq!(mechanism, H::MECHANISM);
+        if username != "user" {
+            return None;
+        }
+        let salt = base64::decode(self.salt).unwrap();
+        Some(ScramCredentials::from_password::<H>("pencil", &salt, 4096))
+    }
+}
+
+fn store<H: ScramHash + Sync>(salt: &'static str) -> Arc<dyn ScramCredentialStore> {
+    Arc::new(Store::<H> {
+        salt,
+        _hash: std::marker::PhantomData,
+    })
+}
+
+fn challenge(step: SaslStep) -> String {
+    match step {
+        SaslStep::Challenge(c) => String::from_utf8(c).unwrap(),
+        _ => panic!("expected a challenge"),
+    }
+}
+
+fn run_exchange(
+    mut mech: Box<dyn SaslMechanism>,
+    client_first: &str,
+    server_first: &str,
+    client_final: &str,
+    server_final: &str,
+) {
+    assert_eq!(challenge(mech.step(None)), "");
+    assert_eq!(
+        challenge(mech.step(Some(client_first.as_bytes()))),
+        server_first
+    );
+    assert_eq!(
+        challenge(mech.step(Some(client_final.as_bytes()))),
+        server_final
+    );
+
+    match mech.step(Some(b"")) {
+        SaslStep::Done(Credentials::Verified { authzid, authcid }) => {
+            assert_eq!(authzid, None);
+            assert_eq!(authcid, "user");
+        }
+        _ => panic!("expected verified credentials"),
+    }
+}
+
+/// RFC 5802 section 5.
+#[test]
+fn scram_sha1_rfc5802() {
+    let mech = ScramSha1::with_server_nonce(
+        store::<sha1::Sha1>("QSXCR+Q6sek8bf92"),
+        "3rfcNHYJY1ZVvWVs7j",
+    );
+
+    run_exchange(
+        Box::new(mech),
+        "n,,n=user,r=fyko+d2lbbFgONRv9qkxdawL",
+        "r=fyko+d2lbbFgONRv9qkxdawL3rfcNHYJY1ZVvWVs7j,s=QSXCR+Q6sek8bf92,i=4096",
+        "c=biws,r=fyko+d2lbbFgONRv9qkxdawL3rfcNHYJY1ZVvWVs7j,p=v0X8v3Bz2T0CJGbJQyF0X+HI4Ts=",
+        "v=rmF9pqV8S7suAoZWja4dJRkFsKQ=",
+    );
+}
+
+/// RFC 7677 section 3.
+#[test]
+fn scram_sha256_rfc7677() {
+    let mech = ScramSha256::with_server_nonce(
+        store::<sha2::Sha256>("W22ZaJ0SNY7soEsUEjb6gQ=="),
+        "%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0",
+    );
+
+    run_exchange(
+        Box::new(mech),
+        "n,,n=user,r=rOprNGfwEbeRWgbNEkqO",
+        "r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096",
+        "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+         p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ=",
+        "v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=",
+    );
+}
+
+#[test]
+fn scram_bad_proof() {
+    let mut mech = ScramSha256::with_server_nonce(
+        store::<sha2::Sha256>("W22ZaJ0SNY7soEsUEjb6gQ=="),
+        "%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0",
+    );
+
+    challenge(mech.step(Some(b"n,,n=user,r=rOprNGfwEbeRWgbNEkqO")));
+    let client_final = "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+                        p=AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+
+    match mech.step(Some(client_final.as_bytes())) {
+        SaslStep::Failed(reply) => assert!(reply.is_error()),
+        _ => panic!("expected a failure"),
+    }
+}
+
+#[test]
+fn scram_unknown_user() {
+    let mut mech = ScramSha1::new(store::<sha1::Sha1>("QSXCR+Q6sek8bf92"));
+
+    let server_first = challenge(mech.step(Some(b"n,,n=nobody,r=abcdef")));
+    let nonce = server_first.split(',').next().unwrap();
+    let client_final = format!("c=biws,{},p=v0X8v3Bz2T0CJGbJQyF0X+HI4Ts=", nonce);
+
+    match mech.step(Some(client_final.as_bytes())) {
+        SaslStep::Failed(reply) => assert!(reply.is_error()),
+        _ => panic!("expected a failure"),
+    }
+}
+
+fn salt_of(mut mech: Box<dyn SaslMechanism>, username: &str) -> String {
+    let client_first = format!("n,,n={},r=abcdef", username);
+    let server_first = challenge(mech.step(Some(client_first.as_bytes())));
+    server_first.split(',').nth(1).unwrap().to_string()
+}
+
+#[test]
+fn scram_unknown_user_salt() {
+    let store = store::<sha2::Sha256>("W22ZaJ0SNY7soEsUEjb6gQ==");
+    let salt = |username| salt_of(Box::new(ScramSha256::new(store.clone())), username);
+
+    // Stable for a user, not derived from the name alone.
+    assert_eq!(salt("nobody"), salt("nobody"));
+    assert_ne!(salt("nobody"), salt("somebody"));
+    let hashed = base64::encode(&sha2::Sha256::digest(b"nobody")[..16]);
+    assert_ne!(salt("nobody"), format!("s={}", hashed));
+}
+
+struct SharedSecret(&'static [u8]);
+
+impl ScramCredentialStore for SharedSecret {
+    fn lookup(&self, _mechanism: &str, _username: &str) -> Option<ScramCredentials> {
+        None
+    }
+
+    fn unknown_user_secret(&self) -> &[u8] {
+        self.0
+    }
+}
+
+#[test]
+fn scram_unknown_user_secret() {
+    let salt = |secret| {
+        salt_of(
+            Box::new(ScramSha1::new(Arc::new(SharedSecret(secret)))),
+            "nobody",
+        )
+    };
+
+    assert_eq!(salt(b"one"), salt(b"one"));
+    assert_ne!(salt(b"one"), salt(b"two"));
+}
+
+#[test]
+fn scram_mandatory_extension() {
+    let mut mech = ScramSha1::new(store::<sha1::Sha1>("QSXCR+Q6sek8bf92"));
+
+    match mech.step(Some(b"n,,m=ext,n=user,r=fyko+d2lbbFgONRv9qkxdawL")) {
+        SaslStep::Failed(reply) => assert!(reply.is_error()),
+        _ => panic!("expected a failure"),
+    }
+}
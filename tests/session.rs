@@ -0,0 +1,89 @@
+mod common;
+
+use common::Test;
+use smtpbis::Session;
+
+#[test]
+fn tracks_session_state() {
+    let mut test = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "STARTTLS\r\n",
+        "EHLO secure.example.org\r\n",
+        "AUTH PLAIN AHVzZXIAcGVuY2ls\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RCPT TO:<b@example.org>\r\n",
+        "RSET\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+    ]);
+    test.handler = common::TestHandler::trusted();
+    test.config.auth_mechanisms = vec!["PLAIN".into()];
+    let out = test.run();
+
+    assert_eq!(out.codes(), [220, 250, 220, 250, 235, 250, 250, 250, 250]);
+    let session = &out.session;
+    assert_eq!(
+        session.peer_addr(),
+        Some("192.0.2.1:40000".parse().unwrap())
+    );
+    assert_eq!(session.socket_peer_addr(), session.peer_addr());
+    assert_eq!(
+        session.local_addr(),
+        Some("198.51.100.1:25".parse().unwrap())
+    );
+    assert_eq!(
+        session.helo().map(ToString::to_string).as_deref(),
+        Some("secure.example.org")
+    );
+    assert!(session.tls());
+    assert_eq!(session.authenticated(), Some("user"));
+    assert_eq!(session.transactions(), 2);
+    assert_eq!(session.error_replies(), 0);
+}
+
+#[test]
+fn starttls_forgets_greeting() {
+    let mut test = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "STARTTLS\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+    ]);
+    test.handler = common::TestHandler::trusted();
+    let out = test.run();
+
+    assert_eq!(out.codes(), [220, 250, 220, 503]);
+    assert!(out.session.tls());
+    assert!(out.session.helo().is_none());
+}
+
+#[test]
+fn counts_errors() {
+    let out = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "FOO\r\n",
+        "MAIL FROM:a@example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RCPT TO:<b@zz.example.org>\r\n",
+        "RCPT TO:<b@example.org>\r\n",
+    ])
+    .run();
+
+    assert_eq!(out.codes(), [220, 250, 500, 500, 250, 550, 250]);
+    let session = &out.session;
+    assert_eq!(session.unknown_commands(), 1);
+    assert_eq!(session.syntax_errors(), 1);
+    assert_eq!(session.rejected_recipients(), 1);
+    assert_eq!(session.error_replies(), 3);
+    assert_eq!(session.transactions(), 1);
+    assert!(!session.improper_pipelining());
+    assert!(!session.early_talker());
+}
+
+#[test]
+fn handler_data() {
+    let mut session = Session::new(None, None, vec![1]);
+    session.data_mut().push(2);
+
+    assert_eq!(session.data(), &[1, 2]);
+    assert_eq!(session.peer_addr(), None);
+    assert_eq!(session.into_data(), [1, 2]);
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use common::Test;
+use smtpbis::Config;
+
+fn size_test(input: &[&str]) -> Test {
+    let mut test = Test::new(input);
+    test.config = Config {
+        max_message_size: Some(20),
+        ..Config::default()
+    };
+    test
+}
+
+#[test]
+fn advertised_and_declared() {
+    let out = size_test(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org> SIZE=21\r\n",
+        "MAIL FROM:<a@example.org> SIZE=20\r\n",
+    ])
+    .run();
+
+    assert!(out.replied("250-SIZE 20"));
+    assert_eq!(out.codes(), [220, 250, 552, 250]);
+}
+
+#[test]
+fn data_too_large() {
+    let out = size_test(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RCPT TO:<b@example.org>\r\n",
+        "DATA\r\n",
+        "0123456789\r\n0123456789\r\n",
+        "0123456789\r\n.\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+    ])
+    .run();
+
+    assert_eq!(out.codes(), [220, 250, 250, 250, 354, 552, 250]);
+    assert!(out.handler.has_event("transaction Aborted 1"));
+    assert!(out.handler.messages.is_empty());
+}
+
+/// The handler reads up to the end of the body, past the error, so
+/// the end of data line is already consumed.
+#[test]
+fn pipelined_commands_after_oversized_last_line() {
+    let mut test = size_test(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RCPT TO:<b@example.org>\r\n",
+        "DATA\r\n",
+        "short\r\n0123456789012345678901234\r\n.\r\nMAIL FROM:<a@example.org>\r\nQUIT\r\n",
+    ]);
+    test.handler.read_past_errors = true;
+    let out = test.run();
+
+    assert_eq!(out.codes(), [220, 250, 250, 250, 354, 552, 250, 221]);
+    assert!(out.result.is_ok());
+}
+
+#[test]
+fn bdat_too_large() {
+    let out = size_test(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RCPT TO:<b@example.org>\r\n",
+        "BDAT 15\r\n012345678901234",
+        "BDAT 6 LAST\r\n012345",
+        "MAIL FROM:<a@example.org>\r\n",
+    ])
+    .run();
+
+    assert_eq!(out.codes(), [220, 250, 250, 250, 250, 552, 250]);
+}
@@ -0,0 +1,44 @@
+mod common;
+
+use common::{Test, TestHandler};
+use smtpbis::ServerError;
+
+fn smtps_test(input: &[&str]) -> Test {
+    let mut test = Test::new(input);
+    test.smtps = true;
+    test.handler = TestHandler::trusted();
+    test
+}
+
+#[test]
+fn handshake_before_banner() {
+    let out = smtps_test(&["EHLO client.example.org\r\n", "QUIT\r\n"]).run();
+
+    assert_eq!(out.codes(), [220, 250, 221]);
+    assert_eq!(out.handler.events[0], "tls");
+    assert!(out.session.tls());
+    assert!(!out.replied("STARTTLS"));
+    assert!(out.result.is_ok());
+}
+
+#[test]
+fn no_tls_config() {
+    let mut test = smtps_test(&["EHLO client.example.org\r\n"]);
+    test.handler = TestHandler::default();
+    let out = test.run();
+
+    assert_eq!(out.output, "");
+    assert!(out.result.is_ok());
+}
+
+/// A failed shutdown does not hide the outcome of the session.
+#[test]
+fn shutdown_error_ignored() {
+    let mut test = smtps_test(&["EHLO client.example.org\r\n", "QUIT\r\n"]);
+    test.fail_shutdown = true;
+    assert!(test.run().result.is_ok());
+
+    let mut test = smtps_test(&["EHLO client.example.org\r\n"]);
+    test.fail_shutdown = true;
+    assert!(matches!(test.run().result, Err(ServerError::EOF)));
+}
@@ -0,0 +1,65 @@
+mod common;
+
+use common::{Test, TestHandler};
+use smtpbis::{Config, ServerError};
+
+fn starttls_test(input: &[&str]) -> Test {
+    let mut test = Test::new(input);
+    test.handler = TestHandler::trusted();
+    test
+}
+
+#[test]
+fn upgrade_restarts_session() {
+    let mut test = starttls_test(&[
+        "EHLO client.example.org\r\n",
+        "AUTH PLAIN AHVzZXIAcGVuY2ls\r\n",
+        "STARTTLS\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "EHLO client.example.org\r\n",
+        "STARTTLS\r\n",
+        "QUIT\r\n",
+    ]);
+    test.config = Config {
+        auth_mechanisms: vec!["PLAIN".into()],
+        ..Config::default()
+    };
+    test.fail_shutdown = true;
+    let out = test.run();
+
+    // No second banner, the client must greet again.
+    assert_eq!(out.codes(), [220, 250, 235, 220, 503, 250, 502, 221]);
+    assert!(out.replied("250-STARTTLS"));
+    assert_eq!(out.output.matches("STARTTLS").count(), 1);
+    assert!(out.handler.has_event("tls"));
+    assert!(out.session.tls());
+    assert_eq!(out.session.authenticated(), None);
+    assert!(out.result.is_ok());
+}
+
+#[test]
+fn no_pipelining_past_starttls() {
+    let out = starttls_test(&[
+        "EHLO client.example.org\r\n",
+        "STARTTLS\r\nEHLO client.example.org\r\n",
+    ])
+    .run();
+
+    assert_eq!(out.codes(), [220, 250]);
+    assert!(!out.handler.has_event("tls"));
+    assert!(matches!(out.result, Err(ServerError::Pipelining)));
+}
+
+#[test]
+fn not_offered() {
+    // Refused by the handler.
+    let out = Test::new(&["EHLO client.example.org\r\n", "STARTTLS\r\n"]).run();
+    assert_eq!(out.codes(), [220, 250, 502]);
+    assert!(!out.session.tls());
+
+    let mut test = starttls_test(&["EHLO client.example.org\r\n", "STARTTLS\r\n"]);
+    test.config.enable_starttls = false;
+    let out = test.run();
+    assert!(!out.replied("STARTTLS"));
+    assert_eq!(out.codes(), [220, 250, 502]);
+}
@@ -0,0 +1,93 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::Test;
+use smtpbis::{PipeliningPolicy, Reply, Tarpit};
+
+fn rejected_recipients() -> Test {
+    let mut test = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RCPT TO:<a@zz.example.org>\r\n",
+        "RCPT TO:<b@zz.example.org>\r\n",
+        "RCPT TO:<c@zz.example.org>\r\n",
+    ]);
+    test.config.tarpit = Some(Tarpit {
+        free_errors: 1,
+        initial: Duration::from_millis(100),
+        increment: Duration::from_millis(100),
+        max: Duration::from_secs(1),
+    });
+    test
+}
+
+fn timed(test: Test) -> (common::Outcome, Duration) {
+    let start = Instant::now();
+    let out = test.run();
+    (out, start.elapsed())
+}
+
+#[test]
+fn delays_error_replies() {
+    let (out, elapsed) = timed(rejected_recipients());
+
+    assert_eq!(out.codes(), [220, 250, 250, 550, 550, 550]);
+    assert_eq!(out.session.error_replies(), 3);
+    // Nothing for the first error, then 100 and 200 ms.
+    assert!(elapsed >= Duration::from_millis(300), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(1), "{:?}", elapsed);
+}
+
+#[test]
+fn handler_delay_overrides() {
+    let mut test = rejected_recipients();
+    test.handler.rcpt_rejection = Some(
+        Reply::new(550, None, "No such user")
+            .with_delay(Duration::from_secs(0))
+            .into(),
+    );
+    let (out, elapsed) = timed(test);
+
+    assert_eq!(out.codes(), [220, 250, 250, 550, 550, 550]);
+    assert_eq!(out.session.error_replies(), 3);
+    assert!(elapsed < Duration::from_millis(100), "{:?}", elapsed);
+}
+
+#[test]
+fn handler_delay_without_tarpit() {
+    let mut test = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RCPT TO:<b@example.org>\r\n",
+        "DATA\r\n",
+        "body\r\n.\r\n",
+    ]);
+    test.handler.message_response = Some(
+        Reply::new(250, None, "Queued")
+            .with_delay(Duration::from_millis(200))
+            .into(),
+    );
+    let (out, elapsed) = timed(test);
+
+    assert_eq!(out.codes(), [220, 250, 250, 250, 354, 250]);
+    assert_eq!(out.session.error_replies(), 0);
+    assert!(elapsed >= Duration::from_millis(200), "{:?}", elapsed);
+}
+
+#[test]
+fn delays_pipelining_rejection() {
+    let mut test = Test::new(&["EHLO client.example.org\r\nNOOP\r\n"]);
+    test.config.pipelining = PipeliningPolicy::Reject;
+    test.config.tarpit = Some(Tarpit {
+        free_errors: 0,
+        initial: Duration::from_millis(200),
+        increment: Duration::from_millis(100),
+        max: Duration::from_secs(1),
+    });
+    let (out, elapsed) = timed(test);
+
+    assert_eq!(out.codes(), [220, 554]);
+    assert_eq!(out.session.error_replies(), 1);
+    assert!(elapsed >= Duration::from_millis(200), "{:?}", elapsed);
+}
@@ -0,0 +1,152 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{Test, TestHandler};
+use smtpbis::{ProxyProtocol, ServerError, Timeouts};
+
+fn hanging(input: &[&str]) -> Test {
+    let mut test = Test::new(input);
+    test.hang = true;
+    test
+}
+
+/// Run `test`, checking that it ended with a timeout within `limit`.
+fn times_out(test: Test, limit: Duration) -> common::Outcome {
+    let start = Instant::now();
+    let out = test.run();
+
+    assert!(start.elapsed() < limit, "took {:?}", start.elapsed());
+    assert!(matches!(out.result, Err(ServerError::Timeout)));
+    out
+}
+
+#[test]
+fn command_timeouts() {
+    let mut test = hanging(&["EHLO client.example.org\r\n"]);
+    test.config.timeouts.mail = Duration::from_millis(50);
+    let out = times_out(test, Duration::from_secs(2));
+    assert_eq!(out.codes(), [220, 250, 421]);
+    assert!(out.replied("421 4.4.2"));
+
+    let mut test = hanging(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+    ]);
+    test.config.timeouts.rcpt = Duration::from_millis(50);
+    let out = times_out(test, Duration::from_secs(2));
+    assert_eq!(out.codes(), [220, 250, 250, 421]);
+    assert!(out.handler.has_event("transaction Aborted 0"));
+}
+
+#[test]
+fn data_timeout() {
+    let mut test = hanging(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RCPT TO:<b@example.org>\r\n",
+        "DATA\r\n",
+        "partial\r\n",
+    ]);
+    test.config.timeouts.data_block = Duration::from_millis(50);
+    let out = times_out(test, Duration::from_secs(2));
+
+    // The handler sees the read fail and replies first.
+    assert_eq!(out.codes(), [220, 250, 250, 250, 354, 451, 421]);
+    assert!(out.handler.messages.is_empty());
+    assert!(out.handler.has_event("transaction Aborted 1"));
+}
+
+#[test]
+fn session_limit_covers_greeting_delay() {
+    let mut test = hanging(&[]);
+    test.config.greeting_delay = Some(Duration::from_secs(10));
+    test.config.timeouts.session = Some(Duration::from_millis(100));
+    let out = times_out(test, Duration::from_secs(2));
+
+    // No banner, only the timeout reply.
+    assert_eq!(out.codes(), [421]);
+}
+
+#[test]
+fn session_limit_covers_proxy_header() {
+    let mut test = hanging(&[]);
+    test.handler.trusted = true;
+    test.config.proxy_protocol = ProxyProtocol::Required(Duration::from_secs(10));
+    test.config.timeouts.session = Some(Duration::from_millis(100));
+    let out = times_out(test, Duration::from_secs(2));
+
+    assert!(out.output.is_empty());
+}
+
+#[test]
+fn session_limit() {
+    let mut test = hanging(&["EHLO client.example.org\r\n"]);
+    test.config.timeouts.session = Some(Duration::from_millis(100));
+    let out = times_out(test, Duration::from_secs(2));
+
+    assert_eq!(out.codes(), [220, 250, 421]);
+}
+
+#[test]
+fn smtps_handshake_timeout() {
+    let mut test = hanging(&[]);
+    test.smtps = true;
+    test.handler = TestHandler::trusted();
+    test.hang_handshake = true;
+    test.config.timeouts.tls_handshake = Duration::from_millis(50);
+    let out = times_out(test, Duration::from_secs(2));
+
+    assert!(out.output.is_empty());
+    assert!(!out.handler.has_event("tls"));
+}
+
+#[test]
+fn starttls_handshake_timeout() {
+    let mut test = hanging(&["EHLO client.example.org\r\n", "STARTTLS\r\n"]);
+    test.handler = TestHandler::trusted();
+    test.hang_handshake = true;
+    test.config.timeouts.session = Some(Duration::from_millis(100));
+    let out = times_out(test, Duration::from_secs(2));
+
+    assert_eq!(out.codes(), [220, 250, 220]);
+    assert!(!out.handler.has_event("tls"));
+}
+
+#[test]
+fn stalled_writes_time_out() {
+    let mut test = Test::new(&["EHLO client.example.org\r\n"]);
+    test.stall_writes = Some(Duration::from_millis(300));
+    test.config.timeouts.session = Some(Duration::from_millis(100));
+    let out = times_out(test, Duration::from_secs(2));
+
+    // The banner never went out, the 421 once the client read again.
+    assert_eq!(out.codes(), [421]);
+}
+
+#[test]
+fn unrepresentable_timeouts() {
+    let mut test = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "RCPT TO:<b@example.org>\r\n",
+        "DATA\r\n",
+        "one\r\n.\r\n",
+        "QUIT\r\n",
+    ]);
+    test.config.timeouts = Timeouts {
+        mail: Duration::MAX,
+        rcpt: Duration::MAX,
+        data_initiation: Duration::MAX,
+        data_block: Duration::MAX,
+        data_termination: Duration::MAX,
+        session: Some(Duration::MAX),
+        min_data_rate: Some(1),
+        ..Timeouts::default()
+    };
+    let out = test.run();
+
+    // No deadline rather than an overflow.
+    assert_eq!(out.codes(), [220, 250, 250, 250, 354, 250, 221]);
+    assert!(out.result.is_ok());
+}
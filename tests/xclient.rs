@@ -0,0 +1,135 @@
+mod common;
+
+use std::net::IpAddr;
+
+use common::Test;
+use smtpbis::{Xclient, XclientParam};
+
+fn param(name: &'static str, value: Option<&str>) -> XclientParam {
+    XclientParam(name, value.map(Into::into))
+}
+
+#[test]
+fn update() {
+    let mut xclient = Xclient::default();
+
+    assert!(xclient
+        .update(vec![
+            param("addr", Some("IPV6:2001:db8::1")),
+            param("port", Some("4000")),
+            param("proto", Some("esmtp")),
+            param("login", Some("user")),
+        ])
+        .is_ok());
+    assert!(xclient
+        .update(vec![param("login", None), param("name", Some("client"))])
+        .is_ok());
+
+    assert_eq!(xclient.addr, Some("2001:db8::1".parse::<IpAddr>().unwrap()));
+    assert_eq!(xclient.port, Some(4000));
+    assert_eq!(xclient.proto.as_deref(), Some("ESMTP"));
+    assert_eq!(xclient.login, None);
+    assert_eq!(xclient.name.as_deref(), Some("client"));
+}
+
+#[test]
+fn invalid_update_changes_nothing() {
+    let mut xclient = Xclient::default();
+
+    // IPv6 addresses need their prefix, IPv4 ones must not have it.
+    for params in [
+        vec![
+            param("name", Some("client")),
+            param("addr", Some("2001:db8::1")),
+        ],
+        vec![param("addr", Some("IPV6:192.0.2.1"))],
+        vec![param("port", Some("65536"))],
+        vec![param("proto", Some("LMTP"))],
+    ] {
+        assert!(xclient.update(params).is_err());
+    }
+    assert_eq!(xclient, Xclient::default());
+}
+
+#[test]
+fn overrides_session() {
+    let mut test = Test::new(&[
+        "EHLO proxy.example.org\r\n",
+        "XCLIENT ADDR=192.0.2.9 PORT=4000 LOGIN=user+40example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "XCLIENT NAME=client.example.org\r\n",
+    ]);
+    test.peer_addr = "127.0.0.1:40000".parse().unwrap();
+    let out = test.run();
+
+    assert!(out.replied("XCLIENT NAME ADDR PORT PROTO HELO LOGIN DESTADDR DESTPORT"));
+    // The session restarts with a new banner and needs a new EHLO.
+    assert_eq!(out.codes(), [220, 250, 220, 503, 250, 250, 503]);
+    assert_eq!(
+        out.session.peer_addr(),
+        Some("192.0.2.9:4000".parse().unwrap())
+    );
+    assert_eq!(
+        out.session.socket_peer_addr(),
+        Some("127.0.0.1:40000".parse().unwrap())
+    );
+    assert_eq!(out.session.authenticated(), Some("user@example.org"));
+}
+
+#[test]
+fn attributes_in_session() {
+    let mut test = Test::new(&[
+        "EHLO proxy.example.org\r\n",
+        "XCLIENT NAME=client.example.org HELO=client.example.org\r\n",
+        "EHLO proxy.example.org\r\n",
+        "XCLIENT PROTO=ESMTP\r\n",
+        "EHLO client.example.org\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+    ]);
+    test.peer_addr = "127.0.0.1:40000".parse().unwrap();
+    let out = test.run();
+
+    assert_eq!(out.codes(), [220, 250, 220, 250, 220, 250, 250]);
+    let xclient = out.handler.mail_xclient.unwrap();
+    assert_eq!(xclient.name.as_deref(), Some("client.example.org"));
+    assert_eq!(xclient.helo.as_deref(), Some("client.example.org"));
+    assert_eq!(xclient.proto.as_deref(), Some("ESMTP"));
+    assert_eq!(out.session.xclient(), Some(&xclient));
+}
+
+/// XCLIENT cannot be used to pose as a trusted address.
+#[test]
+fn authorized_by_socket_address() {
+    let mut test = Test::new(&["EHLO proxy.example.org\r\n", "XCLIENT ADDR=127.0.0.1\r\n"]);
+    let out = test.run();
+    assert_eq!(out.codes(), [220, 250, 502]);
+
+    test = Test::new(&[
+        "EHLO proxy.example.org\r\n",
+        "XCLIENT ADDR=192.0.2.9\r\n",
+        "EHLO client.example.org\r\n",
+        "XCLIENT ADDR=192.0.2.10\r\n",
+    ]);
+    test.peer_addr = "127.0.0.1:40000".parse().unwrap();
+    let out = test.run();
+    assert_eq!(out.codes(), [220, 250, 220, 250, 220]);
+    assert_eq!(
+        out.session.peer_addr().unwrap().ip().to_string(),
+        "192.0.2.10"
+    );
+}
+
+#[test]
+fn rejected_attributes() {
+    let mut test = Test::new(&[
+        "EHLO proxy.example.org\r\n",
+        "XCLIENT ADDR=192.0.2.9 PORT=http\r\n",
+    ]);
+    test.peer_addr = "127.0.0.1:40000".parse().unwrap();
+    let out = test.run();
+
+    assert_eq!(out.codes(), [220, 250, 501]);
+    assert_eq!(out.session.peer_addr(), out.session.socket_peer_addr());
+}
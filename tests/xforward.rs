@@ -0,0 +1,83 @@
+mod common;
+
+use rustyknife::xforward::Param;
+
+use common::{Test, TestHandler};
+use smtpbis::Xforward;
+
+fn param(name: &'static str, value: Option<&str>) -> Param {
+    Param(name, value.map(Into::into))
+}
+
+#[test]
+fn update() {
+    let mut xforward = Xforward::default();
+
+    assert!(xforward
+        .update(vec![
+            param("name", Some("client.example.org")),
+            param("port", Some("2525")),
+            param("source", Some("remote")),
+        ])
+        .is_ok());
+    assert!(xforward
+        .update(vec![param("name", None), param("helo", Some("client"))])
+        .is_ok());
+
+    assert_eq!(xforward.name, None);
+    assert_eq!(xforward.port, Some(2525));
+    assert_eq!(xforward.source.as_deref(), Some("REMOTE"));
+    assert_eq!(xforward.helo.as_deref(), Some("client"));
+}
+
+#[test]
+fn invalid_update_changes_nothing() {
+    let mut xforward = Xforward::default();
+
+    assert!(xforward
+        .update(vec![
+            param("addr", Some("192.0.2.1")),
+            param("port", Some("x"))
+        ])
+        .is_err());
+    assert!(xforward
+        .update(vec![param("source", Some("elsewhere"))])
+        .is_err());
+    assert_eq!(xforward, Xforward::default());
+}
+
+#[test]
+fn attributes_in_envelope() {
+    let mut test = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "XFORWARD NAME=origin.example.org ADDR=192.0.2.7\r\n",
+        "XFORWARD PORT=25 PROTO=ESMTP HELO=origin\r\n",
+        "XFORWARD PORT=bad\r\n",
+        "MAIL FROM:<a@example.org>\r\n",
+        "XFORWARD NAME=[UNAVAILABLE]\r\n",
+        "RSET\r\n",
+    ]);
+    test.handler = TestHandler::trusted();
+    let out = test.run();
+
+    assert!(out.replied("XFORWARD NAME ADDR PORT PROTO HELO IDENT SOURCE"));
+    assert_eq!(out.codes(), [220, 250, 250, 250, 501, 250, 503, 250]);
+
+    let xforward = &out.handler.envelopes[0].xforward;
+    assert_eq!(xforward.name.as_deref(), Some("origin.example.org"));
+    assert_eq!(xforward.addr.as_deref(), Some("192.0.2.7"));
+    assert_eq!(xforward.port, Some(25));
+    assert_eq!(xforward.helo.as_deref(), Some("origin"));
+}
+
+#[test]
+fn untrusted_peer() {
+    let out = Test::new(&[
+        "EHLO client.example.org\r\n",
+        "XFORWARD NAME=origin.example.org\r\n",
+    ])
+    .run();
+
+    assert!(!out.replied("XFORWARD"));
+    assert_eq!(out.codes(), [220, 250, 502]);
+}